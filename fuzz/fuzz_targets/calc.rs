@@ -0,0 +1,19 @@
+//! Feed arbitrary input to the calc engine, which must report errors rather than panic. Run with
+//! `cargo fuzz run calc`.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate extrautils;
+
+use std::str;
+
+use extrautils::calc::{tokenize, Evaluator, Parser};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = str::from_utf8(data) {
+        if let Ok(tokens) = tokenize(input) {
+            let _ = Parser::new(&tokens).parse_statement();
+        }
+        let _ = Evaluator::new().run(input);
+    }
+});
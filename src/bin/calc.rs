@@ -1,272 +1,624 @@
 #![deny(warnings)]
 extern crate extra;
+extern crate extrautils;
+extern crate termion;
 use extra::option::OptionalExt;
 use extra::io::{fail, WriteExt};
-
-use std::env::args;
-use std::io::{self, Write};
-
-#[derive(Debug, Clone)]
-pub enum Token {
-    Plus,
-    Minus,
-    Divide,
-    Multiply,
-    Exponent,
-    OpenParen,
-    CloseParen,
-    Number(String),
+use extrautils::calc::{find_function, format_offset, parse_offset, Evaluator, Notation, Span, Value};
+
+use std::cmp;
+use std::env::{self, args};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use termion::{clear, cursor};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+
+/// The text being edited on the prompt line and the cursor position within it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineBuffer {
+    chars: Vec<char>,
+    cursor: usize,
 }
 
-impl Token {
-    pub fn to_str(&self) -> &'static str {
-        match self {
-            &Token::Plus       => "Plus",
-            &Token::Minus      => "Minus",
-            &Token::Divide     => "Divide",
-            &Token::Multiply   => "Multiply",
-            &Token::Exponent   => "Exponent",
-            &Token::OpenParen  => "OpenParen",
-            &Token::CloseParen => "CloseParen",
-            &Token::Number(_)  => "Number",
+impl LineBuffer {
+    pub fn new() -> Self {
+        LineBuffer {
+            chars: Vec::new(),
+            cursor: 0,
         }
     }
 
-    pub fn to_string(&self) -> String {
-        self.to_str().to_owned()
+    /// A buffer holding `text`, with the cursor at the end.
+    pub fn from_text(text: &str) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        LineBuffer {
+            cursor: chars.len(),
+            chars: chars,
+        }
     }
-}
 
-#[derive(Debug,  Clone)]
-pub enum ParseError {
-    InvalidNumber(String),
-    UnrecognizedToken(String),
-    UnexpectedToken(String, &'static str),
-    UnexpectedEndOfInput,
-    OtherError(String),
-}
+    pub fn text(&self) -> String {
+        self.chars.iter().cloned().collect()
+    }
 
-#[derive(Clone,Debug)]
-pub struct IntermediateResult {
-    value: f64,
-    tokens_read: usize,
-}
+    pub fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn left(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+        }
+    }
 
-impl IntermediateResult {
-    fn new(value: f64, tokens_read: usize) -> Self {
-        IntermediateResult {
-            value: value,
-            tokens_read: tokens_read,
+    pub fn right(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.cursor += 1;
         }
     }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.chars.len();
+    }
+
+    pub fn kill_to_end(&mut self) {
+        self.chars.truncate(self.cursor);
+    }
+
+    pub fn kill_to_start(&mut self) {
+        self.chars.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    /// Delete the word before the cursor, along with any whitespace following it.
+    pub fn delete_word(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars.drain(start..self.cursor);
+        self.cursor = start;
+    }
 }
 
-pub trait OperatorFunctions {
-    fn is_operator(self) -> bool;
-    fn operator_type(self) -> Token;
+// The number of lines kept in the history file
+const HISTORY_SIZE: usize = 1000;
+
+/// Previously entered lines, oldest first, optionally persisted to a file.
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
 }
 
-impl OperatorFunctions for char {
-    fn is_operator(self) -> bool {
-        self == '+' ||
-        self == '-' ||
-        self == '*' ||
-        self == '/' ||
-        self == '^' ||
-        self == '(' ||
-        self == ')'
-    }
-
-    fn operator_type(self) -> Token {
-        match self {
-            '+' => Token::Plus,
-            '-' => Token::Minus,
-            '/' => Token::Divide,
-            '*' => Token::Multiply,
-            '^' => Token::Exponent,
-            '(' => Token::OpenParen,
-            ')' => Token::CloseParen,
-            _   => fail("Invalid operator", &mut io::stderr())
+impl History {
+    pub fn new() -> Self {
+        History {
+            entries: Vec::new(),
+            path: None,
+        }
+    }
+
+    /// Load the history from `~/.calc_history`, which new entries are then appended to.
+    pub fn load() -> Self {
+        match env::var("HOME") {
+            Ok(home) => History::open(Path::new(&home).join(".calc_history")),
+            Err(_) => History::new(),
+        }
+    }
+
+    /// Load the history from `path`, rewriting the file if it holds more than `HISTORY_SIZE`
+    /// entries so that appending to it does not grow it forever.
+    pub fn open(path: PathBuf) -> Self {
+        let mut entries: Vec<String> = match File::open(&path) {
+            Ok(file) => BufReader::new(file).lines().filter_map(|line| line.ok()).collect(),
+            Err(_) => Vec::new(),
+        };
+        if entries.len() > HISTORY_SIZE {
+            let excess = entries.len() - HISTORY_SIZE;
+            entries.drain(..excess);
+
+            // As with appending, a history that cannot be saved is only kept for the session
+            if let Ok(mut file) = File::create(&path) {
+                let mut text = entries.join("\n");
+                text.push('\n');
+                let _ = file.write_all(text.as_bytes());
+            }
+        }
+
+        History {
+            entries: entries,
+            path: Some(path),
         }
     }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, index: usize) -> &str {
+        &self.entries[index]
+    }
+
+    /// Add a line, skipping blank lines and repeats of the previous line.
+    pub fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.entries.last().map_or(false, |last| last == line) {
+            return;
+        }
+        self.entries.push(line.to_owned());
+        if self.entries.len() > HISTORY_SIZE {
+            self.entries.remove(0);
+        }
+
+        // Failing to save the history should not interrupt the session
+        if let Some(ref path) = self.path {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = file.write_all([line, "\n"].concat().as_bytes());
+            }
+        }
+    }
+
+    /// The index of the newest entry before `before` which contains `query`, ignoring case.
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        let query = query.to_lowercase();
+        self.entries[..before].iter().rposition(|entry| entry.to_lowercase().contains(&query))
+    }
 }
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
-    let mut tokens = Vec::with_capacity(input.len());
-
-    // TODO: Not this. Modify to use iterator
-    let chars: Vec<char> = input.chars().collect();
-
-    let input_length = chars.len();
-    let mut current_pos = 0;
-    while current_pos < input_length {
-        let c = chars[current_pos];
-        if c.is_digit(10) || c == '.' {
-            let token_string = consume_number(&chars[current_pos..]);
-            current_pos += token_string.len();
-            tokens.push(Token::Number(token_string));
-        } else if c.is_operator() {
-            tokens.push(c.operator_type());
-            current_pos += 1;
-        } else if c.is_whitespace() {
-            current_pos += 1;
-        } else {
-            let token_string = consume_until_new_token(&chars[current_pos..]);
-            return Err(ParseError::UnrecognizedToken(token_string));
-        }
-    }
-    Ok(tokens)
+fn redraw<W: Write>(out: &mut W, prompt: &str, line: &LineBuffer) -> io::Result<()> {
+    try!(write!(out, "\r{}{}{}", clear::CurrentLine, prompt, line.text()));
+    let back = line.len() - line.cursor();
+    if back > 0 {
+        try!(write!(out, "{}", cursor::Left(back as u16)));
+    }
+    out.flush()
 }
 
-fn consume_number(input: &[char]) -> String {
-    let mut number = String::with_capacity(input.len());
-    let mut has_decimal_point = false;
-    for &c in input {
-        if c == '.' {
-            if has_decimal_point {
-                break;
-            } else {
-                number.push(c);
-                has_decimal_point = true;
+fn redraw_search<W: Write>(out: &mut W, query: &str, found: Option<&str>) -> io::Result<()> {
+    try!(write!(out, "\r{}(reverse-i-search)`{}': {}", clear::CurrentLine, query, found.unwrap_or("")));
+    out.flush()
+}
+
+/// Read a line from the terminal in raw mode, with Emacs style cursor movement, history recall
+/// with the arrow keys and reverse history search with Ctrl-R. Returns `None` at end of input.
+pub fn read_line<W: Write>(prompt: &str, history: &mut History, stdout: &mut W) -> io::Result<Option<String>> {
+    let mut terminal = try!(stdout.into_raw_mode());
+    let stdin = io::stdin();
+    let mut keys = stdin.lock().keys();
+
+    let mut line = LineBuffer::new();
+    // The history entry being shown, and the line which was being edited before browsing
+    let mut history_index = history.len();
+    let mut edited = String::new();
+    // The reverse search query and the entry it found, while searching
+    let mut search: Option<String> = None;
+    let mut found: Option<usize> = None;
+
+    try!(redraw(&mut terminal, prompt, &line));
+    loop {
+        let key = match keys.next() {
+            Some(key) => try!(key),
+            None => return Ok(None),
+        };
+
+        let key = match search.take() {
+            Some(mut query) => match key {
+                Key::Char(c) if c != '\n' => {
+                    query.push(c);
+                    found = history.search(&query, history.len());
+                    try!(redraw_search(&mut terminal, &query, found.map(|i| history.get(i))));
+                    search = Some(query);
+                    continue;
+                }
+                Key::Backspace => {
+                    query.pop();
+                    found = history.search(&query, history.len());
+                    try!(redraw_search(&mut terminal, &query, found.map(|i| history.get(i))));
+                    search = Some(query);
+                    continue;
+                }
+                Key::Ctrl('r') => {
+                    found = history.search(&query, found.unwrap_or(history.len())).or(found);
+                    try!(redraw_search(&mut terminal, &query, found.map(|i| history.get(i))));
+                    search = Some(query);
+                    continue;
+                }
+                Key::Ctrl('g') | Key::Esc => {
+                    try!(redraw(&mut terminal, prompt, &line));
+                    continue;
+                }
+                // Any other key takes the match and is then handled as usual
+                key => {
+                    if let Some(i) = found {
+                        line = LineBuffer::from_text(history.get(i));
+                        history_index = history.len();
+                    }
+                    key
+                }
+            },
+            None => key,
+        };
+
+        match key {
+            Key::Char('\n') => {
+                try!(terminal.write_all(b"\r\n"));
+                let text = line.text();
+                history.push(&text);
+                return Ok(Some(text));
             }
-        } else if c.is_digit(10) {
-            number.push(c);
-        } else {
-            break;
+            Key::Char(c) => line.insert(c),
+            Key::Backspace | Key::Ctrl('h') => line.backspace(),
+            Key::Delete => line.delete(),
+            Key::Left | Key::Ctrl('b') => line.left(),
+            Key::Right | Key::Ctrl('f') => line.right(),
+            Key::Home | Key::Ctrl('a') => line.home(),
+            Key::End | Key::Ctrl('e') => line.end(),
+            Key::Ctrl('k') => line.kill_to_end(),
+            Key::Ctrl('u') => line.kill_to_start(),
+            Key::Ctrl('w') => line.delete_word(),
+            Key::Up | Key::Ctrl('p') => {
+                if history_index > 0 {
+                    if history_index == history.len() {
+                        edited = line.text();
+                    }
+                    history_index -= 1;
+                    line = LineBuffer::from_text(history.get(history_index));
+                }
+            }
+            Key::Down | Key::Ctrl('n') => {
+                if history_index < history.len() {
+                    history_index += 1;
+                    line = if history_index == history.len() {
+                        LineBuffer::from_text(&edited)
+                    } else {
+                        LineBuffer::from_text(history.get(history_index))
+                    };
+                }
+            }
+            Key::Ctrl('r') => {
+                search = Some(String::new());
+                found = None;
+                try!(redraw_search(&mut terminal, "", None));
+                continue;
+            }
+            Key::Ctrl('c') => {
+                try!(terminal.write_all(b"^C\r\n"));
+                line = LineBuffer::new();
+                history_index = history.len();
+            }
+            Key::Ctrl('d') => {
+                if line.is_empty() {
+                    try!(terminal.write_all(b"\r\n"));
+                    return Ok(None);
+                }
+                line.delete();
+            }
+            _ => (),
         }
+        try!(redraw(&mut terminal, prompt, &line));
     }
-    number
 }
 
-fn consume_until_new_token(input: &[char]) -> String {
-    input.iter()
-         .take_while(|c| !(c.is_whitespace() || c.is_operator() || c.is_digit(10)))
-         .map(|&c| c)
-         .collect()
+// Evaluate a line, or describe the error with the input and a caret marking where it went wrong
+fn eval(input: &str, evaluator: &mut Evaluator) -> Result<String, String> {
+    evaluator.run(input).map_err(|e| {
+        [e.to_string(), "\n".to_owned(), input.to_owned(), "\n".to_owned(), caret(input, e.span)].concat()
+    })
+}
+
+// A line marking the span below the input it refers to
+fn caret(input: &str, span: Span) -> String {
+    let offset = input[..span.start].chars().count();
+    let width = cmp::max(1, input[span.start..span.end].chars().count());
+    [" ".repeat(offset), "^".repeat(width)].concat()
+}
+
+
+// An expression given on the command line, which keeps its words apart so that `90min in s` does not
+// run together into `90mininss`
+fn expression(args: &[String]) -> String {
+    args.join(" ")
 }
 
-// Addition and subtraction
-pub fn e_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
-    let mut t1 = try!(t_expr(token_list));
-    let mut index = t1.tokens_read;
-
-    while index < token_list.len() {
-        match token_list[index] {
-            Token::Plus => {
-                let t2 = try!(t_expr(&token_list[index+1..]));
-                t1.value += t2.value;
-                t1.tokens_read += t2.tokens_read + 1;
+// REPL commands, which start with a colon
+fn command(input: &str, evaluator: &mut Evaluator) -> Result<String, String> {
+    let env = evaluator.environment_mut();
+    let mut words = input[1..].split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("base"), Some(radix)) => {
+            match radix.parse::<u32>() {
+                Ok(radix) => match env.format.set_radix(radix) {
+                    Ok(()) => Ok(["Output base set to ", &radix.to_string()].concat()),
+                    Err(e) => Err(e.to_string()),
+                },
+                Err(_) => Err(["Error: Invalid base: ", radix].concat()),
             }
-            Token::Minus => {
-                let t2 = try!(t_expr(&token_list[index+1..]));
-                t1.value -= t2.value;
-                t1.tokens_read += t2.tokens_read + 1;
+        }
+        (Some("base"), None) => Ok(["Output base is ", &env.format.radix.to_string()].concat()),
+        (Some("precision"), Some("off")) => {
+            env.format.precision = None;
+            Ok("Precision cleared".to_owned())
+        }
+        (Some("precision"), Some(digits)) => match digits.parse::<usize>() {
+            Ok(precision) => {
+                env.format.precision = Some(precision);
+                Ok(["Precision set to ", digits].concat())
             }
-            Token::Number(ref n) => return Err(ParseError::UnexpectedToken(n.clone(),"operator")),
-            _ => break,
+            Err(_) => Err(["Error: Invalid precision: ", digits].concat()),
+        },
+        (Some("precision"), None) => Ok(match env.format.precision {
+            Some(precision) => ["Precision is ", &precision.to_string()].concat(),
+            None => "Precision is unlimited".to_owned(),
+        }),
+        (Some("plain"), None) => {
+            env.format.notation = Notation::Plain;
+            Ok("Plain notation".to_owned())
+        }
+        (Some("sci"), None) => {
+            env.format.notation = Notation::Scientific;
+            Ok("Scientific notation".to_owned())
+        }
+        (Some("eng"), None) => {
+            env.format.notation = Notation::Engineering;
+            Ok("Engineering notation".to_owned())
+        }
+        (Some("group"), None) => {
+            env.format.group = !env.format.group;
+            Ok((if env.format.group { "Digit grouping on" } else { "Digit grouping off" }).to_owned())
+        }
+        (Some("offset"), Some(offset)) => match parse_offset(offset) {
+            Some(seconds) => {
+                env.format.offset = seconds;
+                Ok(["UTC offset set to ", &format_offset(seconds)].concat())
+            }
+            None => Err(["Error: Invalid UTC offset: ", offset].concat()),
+        },
+        (Some("offset"), None) => Ok(["UTC offset is ", &format_offset(env.format.offset)].concat()),
+        (Some("vars"), None) => {
+            let lines: Vec<String> = env.variables().iter()
+                                        .map(|&(name, value)| [name, " = ", &env.format.value(value)].concat())
+                                        .collect();
+            Ok(if lines.is_empty() { "No variables defined".to_owned() } else { lines.join("\n") })
+        }
+        (Some("funcs"), None) => {
+            let lines: Vec<String> = env.functions().iter()
+                                        .map(|&(name, f)| format!("{}({}) = {}", name, f.params.join(", "), f.body))
+                                        .collect();
+            Ok(if lines.is_empty() { "No functions defined".to_owned() } else { lines.join("\n") })
+        }
+        _ => Err(["Error: Unknown command: ", input].concat()),
+    }
+}
+
+// Whether a command changes a setting, rather than showing one or listing definitions. Only the
+// REPL confirms these, so that batch output holds nothing but results.
+fn changes_setting(input: &str) -> bool {
+    let mut words = input[1..].split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("base"), Some(_)) | (Some("precision"), Some(_)) | (Some("offset"), Some(_)) => true,
+        (Some("plain"), None) | (Some("sci"), None) | (Some("eng"), None) | (Some("group"), None) => true,
+        _ => false,
+    }
+}
+
+// The part of a line before any comment, which starts with `#`
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+// Evaluate a line of input, which is either a command or an expression
+fn run_line(line: &str, evaluator: &mut Evaluator) -> Option<Result<String, String>> {
+    match strip_comment(line).trim() {
+        "" => None,
+        s if s.starts_with(':') => Some(command(s, evaluator)),
+        s => Some(eval(s, evaluator)),
+    }
+}
+
+// Evaluate one line at a time with no prompts, printing each result on its own line and stopping
+// at the first error, which is reported with the name of the input and the line number
+fn batch<R: BufRead, W: Write>(input: R, name: &str, evaluator: &mut Evaluator, out: &mut W) -> Result<(), String> {
+    for (i, line) in input.lines().enumerate() {
+        let line = try!(line.map_err(|e| format!("{}: {}", name, e)));
+        if line.trim() == "exit" {
+            break;
+        }
+        match run_line(&line, evaluator) {
+            Some(Ok(_)) if changes_setting(strip_comment(&line).trim()) => continue,
+            Some(Ok(result)) => try!(out.writeln(result.as_bytes()).map_err(|e| format!("{}: {}", name, e))),
+            Some(Err(e)) => return Err(format!("{}:{}: {}", name, i + 1, e)),
+            None => continue,
         };
-        index = t1.tokens_read;
     }
-    Ok(t1)
+    Ok(())
 }
 
-// Multiplication and division
-pub fn t_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
-    let mut f1 = try!(f_expr(token_list));
-    let mut index = f1.tokens_read;
-
-    while index < token_list.len() {
-        match token_list[index] {
-            Token::Multiply => {
-                let f2 = try!(f_expr(&token_list[index+1..]));
-                f1.value *= f2.value;
-                f1.tokens_read += f2.tokens_read + 1;
+// The statistics printed by --stats, which are the variadic functions of the same names
+const SUMMARY: [&'static str; 7] = ["sum", "mean", "median", "min", "max", "stddev", "var"];
+
+// Read whitespace separated numbers and summarize them, one statistic per line
+fn summarize<R: BufRead>(input: R, name: &str, evaluator: &Evaluator) -> Result<String, String> {
+    let mut values = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = try!(line.map_err(|e| format!("{}: {}", name, e)));
+        for word in line.split_whitespace() {
+            let value = try!(evaluator.environment().number(word).map_err(|e| format!("{}:{}: {}", name, i + 1, e)));
+            values.push(value.to_f64());
+        }
+    }
+    if values.is_empty() {
+        return Err(format!("{}: Error: No numbers given", name));
+    }
+
+    let mut lines = vec![format!("{:<8}{}", "count", values.len())];
+    for statistic in SUMMARY.iter() {
+        let function = find_function(statistic).unwrap();
+        if values.len() < function.min_args {
+            return Err(format!("{}: Error: {} needs at least {} numbers", name, statistic, function.min_args));
+        }
+        let value = Value::Float((function.apply)(&values));
+        lines.push(format!("{:<8}{}", statistic, evaluator.format(&value)));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn main() {
+    let mut args: Vec<String> = args().skip(1).collect();
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut stderr = io::stderr();
+    let mut evaluator = Evaluator::new();
+    let mut file = None;
+    let mut test = false;
+    let mut stats = false;
+    while !args.is_empty() {
+        let env = evaluator.environment_mut();
+        match args[0].as_str() {
+            "--exact" => env.set_exact(true),
+            "--hex" => env.format.set_radix(16).unwrap(),
+            "--oct" => env.format.set_radix(8).unwrap(),
+            "--bin" => env.format.set_radix(2).unwrap(),
+            "--dec" => env.format.set_radix(10).unwrap(),
+            "--test" => test = true,
+            "--sci" => env.format.notation = Notation::Scientific,
+            "--eng" => env.format.notation = Notation::Engineering,
+            "--group" => env.format.group = true,
+            "--precision" => {
+                let precision = args.get(1).and_then(|digits| digits.parse::<usize>().ok());
+                env.format.precision = Some(precision.fail("--precision requires a number of digits", &mut stderr));
+                args.remove(1);
             }
-            Token::Divide => {
-                let f2 = try!(f_expr(&token_list[index+1..]));
-                if f2.value == 0.0 {
-                    return Err(ParseError::OtherError("Divide by zero error".to_owned()));
-                } else {
-                    f1.value /= f2.value;
-                    f1.tokens_read += f2.tokens_read + 1;
+            "--utc-offset" => {
+                let offset = args.get(1).and_then(|offset| parse_offset(offset));
+                env.format.offset = offset.fail("--utc-offset requires an offset such as +02:00", &mut stderr);
+                args.remove(1);
+            }
+            "--stats" => stats = true,
+            "-f" | "--file" => {
+                if args.len() < 2 {
+                    fail("option requires an argument -- 'f'", &mut stderr);
                 }
+                file = Some(args.remove(1));
             }
-            Token::Number(ref n) => return Err(ParseError::UnexpectedToken(n.clone(),"operator")),
             _ => break,
         }
-        index = f1.tokens_read;
+        args.remove(0);
     }
-    Ok(f1)
-}
 
-// Exponentiation
-pub fn f_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
-    let mut g1 = try!(g_expr(token_list));
-    let mut index = g1.tokens_read;
-    let token_len = token_list.len();
-    while index < token_len {
-        match token_list[index] {
-            Token::Exponent => {
-                let f = try!(f_expr(&token_list[index+1..]));
-                g1.value = g1.value.powf(f.value);
-                g1.tokens_read += f.tokens_read + 1;
-            }
-            Token::Number(ref n) => return Err(ParseError::UnexpectedToken(n.clone(),"operator")),
-            _ => break,
+    // Like test(1), exit with 0 for true, 1 for false and 2 for errors, printing nothing else
+    if test {
+        if args.is_empty() || file.is_some() {
+            fail("--test requires an expression", &mut stderr);
         }
-        index = g1.tokens_read;
+        let status = match eval(&expression(&args), &mut evaluator) {
+            Ok(_) => match evaluator.environment().get("ans") {
+                Ok(Value::Bool(b)) => if b { 0 } else { 1 },
+                _ => {
+                    stderr.writeln(b"Error: --test requires a boolean result").try(&mut stderr);
+                    2
+                }
+            },
+            Err(e) => {
+                stderr.writeln(e.as_bytes()).try(&mut stderr);
+                2
+            }
+        };
+        exit(status);
     }
-    Ok(g1)
-}
 
-// Numbers and parenthesized expressions
-pub fn g_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
-    if !token_list.is_empty() {
-        match token_list[0] {
-            Token::Number(ref n) => {
-                n.parse::<f64>()
-                 .map_err(|_| ParseError::InvalidNumber(n.clone()))
-                 .and_then(|num| Ok(IntermediateResult::new(num, 1)))
+    let result = if stats {
+        if !args.is_empty() {
+            fail("--stats reads numbers from standard input or a file", &mut stderr);
+        }
+        match file {
+            Some(path) => summarize(BufReader::new(File::open(&path).try(&mut stderr)), &path, &evaluator),
+            None => {
+                let stdin = io::stdin();
+                let input = stdin.lock();
+                summarize(input, "<stdin>", &evaluator)
             }
-            Token::Minus => {
-                if token_list.len() > 1 {
-                    if let Token::Number(ref n) = token_list[1] {
-                        n.parse::<f64>()
-                         .map_err(|_| ParseError::InvalidNumber(n.clone()))
-                         .and_then(|num| Ok(IntermediateResult::new(-1.0 * num, 2)))
-                    } else {
-                        Err(ParseError::UnexpectedToken(token_list[1].to_string(), "number"))
-                    }
-                } else {
-                    Err(ParseError::UnexpectedEndOfInput)
-                }
+        }.map(|summary| {
+            stdout.writeln(summary.as_bytes()).try(&mut stderr);
+        })
+    } else if let Some(path) = file {
+        let input = File::open(&path).try(&mut stderr);
+        batch(BufReader::new(input), &path, &mut evaluator, &mut stdout)
+    } else if !args.is_empty() {
+        eval(&expression(&args), &mut evaluator).and_then(|result| {
+            stdout.writeln(result.as_bytes()).try(&mut stderr);
+            Ok(())
+        })
+    } else if !termion::is_tty(&io::stdin()) {
+        let stdin = io::stdin();
+        let input = stdin.lock();
+        batch(input, "<stdin>", &mut evaluator, &mut stdout)
+    } else {
+        let prompt = "[]> ";
+        let mut history = History::load();
+        loop {
+            let input = match read_line(prompt, &mut history, &mut stdout).try(&mut stderr) {
+                Some(line) => line,
+                None => break,
+            };
+            if input.trim() == "exit" {
+                break;
             }
-            Token::OpenParen => {
-                let expr = e_expr(&token_list[1..]);
-                match expr {
-                    Ok(ir) => {
-                        let close_paren = ir.tokens_read + 1;
-                        if close_paren < token_list.len() {
-                            match token_list[close_paren] {
-                                Token::CloseParen => Ok(IntermediateResult::new(ir.value, close_paren+1)),
-                                _ => Err(ParseError::UnexpectedToken(token_list[close_paren].to_string(), ")")),
-                            }
-                        } else {
-                            Err(ParseError::OtherError("no matching close parenthesis found.".to_owned()))
-                        }
-                    }
-                    Err(e) => Err(e),
+            match run_line(&input, &mut evaluator) {
+                Some(Ok(result)) | Some(Err(result)) => {
+                    stdout.writeln(result.as_bytes()).try(&mut stderr);
+                    stdout.flush().try(&mut stderr);
                 }
+                None => (),
             }
-            _ => Err(ParseError::UnexpectedToken(token_list[0].to_string(), "number"))
         }
-    } else {
-        Err(ParseError::UnexpectedEndOfInput)
-    }
-}
+        Ok(())
+    };
 
-pub fn parse(tokens: Vec<Token>) -> Result<String, ParseError> {
-    e_expr(&tokens).map(|answer| answer.value.to_string())
+    if let Err(e) = result {
+        stdout.flush().try(&mut stderr);
+        stderr.writeln(e.as_bytes()).try(&mut stderr);
+        exit(1);
+    }
 }
 
 #[cfg(test)]
@@ -274,109 +626,138 @@ mod test {
     use super::*;
 
     #[test]
-    fn simple_addition() {
-        assert_eq!(tokenize("12+3").and_then(parse).unwrap(), "15");
-    }
-
-    #[test]
-    fn addition() {
-        assert_eq!(tokenize("12+3+5").and_then(parse).unwrap(), "20");
+    fn introspection() {
+        let mut evaluator = Evaluator::new();
+        assert_eq!(command(":vars", &mut evaluator).unwrap(), "No variables defined");
+        assert_eq!(command(":funcs", &mut evaluator).unwrap(), "No functions defined");
+        for input in &["y = 2", "x = 1", "sq(n) = n*n", "cube(n) = n^3"] {
+            evaluator.run(input).unwrap();
+        }
+        assert_eq!(command(":vars", &mut evaluator).unwrap(), "ans = 1\nx = 1\ny = 2");
+        assert_eq!(command(":funcs", &mut evaluator).unwrap(), "cube(n) = n^3\nsq(n) = n * n");
     }
 
     #[test]
-    fn simple_subtraction() {
-        assert_eq!(tokenize("12-3").and_then(parse).unwrap(), "9");
+    fn format_commands() {
+        let mut evaluator = Evaluator::new();
+        let run = |input: &str, evaluator: &mut Evaluator| evaluator.run(input).unwrap();
+        assert_eq!(command(":precision 2", &mut evaluator).unwrap(), "Precision set to 2");
+        assert_eq!(run("2/3", &mut evaluator), "0.67");
+        assert_eq!(command(":sci", &mut evaluator).unwrap(), "Scientific notation");
+        assert_eq!(run("123456", &mut evaluator), "1.23e5");
+        assert_eq!(command(":eng", &mut evaluator).unwrap(), "Engineering notation");
+        assert_eq!(run("123456", &mut evaluator), "123e3");
+        assert_eq!(command(":plain", &mut evaluator).unwrap(), "Plain notation");
+        assert_eq!(command(":precision off", &mut evaluator).unwrap(), "Precision cleared");
+        assert_eq!(command(":group", &mut evaluator).unwrap(), "Digit grouping on");
+        assert_eq!(run("2^20", &mut evaluator), "1,048,576");
+        assert_eq!(run("1.5MB in B", &mut evaluator), "1,500,000 B");
+        assert!(command(":precision many", &mut evaluator).is_err());
+        assert_eq!(command(":offset", &mut evaluator).unwrap(), "UTC offset is Z");
+        assert_eq!(command(":offset +02:00", &mut evaluator).unwrap(), "UTC offset set to +02:00");
+        assert_eq!(run("2026-10-17T09:30 + 1h", &mut evaluator), "2026-10-17T10:30:00+02:00");
+        assert!(command(":offset 2h", &mut evaluator).is_err());
     }
 
     #[test]
-    fn subtraction() {
-        assert_eq!(tokenize("12-3-4").and_then(parse).unwrap(), "5");
+    fn summary() {
+        let summary = summarize("3 1\n  2\n\n5\t4\n".as_bytes(), "<stdin>", &Evaluator::new()).unwrap();
+        assert_eq!(summary, "count   5\nsum     15\nmean    3\nmedian  3\nmin     1\nmax     5\n\
+                             stddev  1.5811388300841898\nvar     2.5");
+        assert_eq!(summarize("1\n2 x\n".as_bytes(), "col.txt", &Evaluator::new()).unwrap_err(),
+                   "col.txt:2: Error: Invalid number: x");
+        assert_eq!(summarize("\n".as_bytes(), "<stdin>", &Evaluator::new()).unwrap_err(),
+                   "<stdin>: Error: No numbers given");
+        assert_eq!(summarize("7\n".as_bytes(), "<stdin>", &Evaluator::new()).unwrap_err(),
+                   "<stdin>: Error: stddev needs at least 2 numbers");
     }
 
     #[test]
-    fn mixed_addition_and_subtraction() {
-        assert_eq!(tokenize("12+3-4+8-2-3").and_then(parse).unwrap(), "14");
+    fn caret_diagnostics() {
+        assert_eq!(eval("1 + foo", &mut Evaluator::new()).unwrap_err(),
+                   "Error: Unknown variable: foo\n1 + foo\n    ^^^");
+        assert_eq!(eval("2 *", &mut Evaluator::new()).unwrap_err(),
+                   "Error: Unexpected end of input.\n2 *\n   ^");
     }
 
     #[test]
-    fn simple_parentheses() {
-        assert_eq!(tokenize("((3))").and_then(parse).unwrap(), "3");
-        assert_eq!(tokenize("(12+(2+3))").and_then(parse).unwrap(), "17");
-        assert_eq!(tokenize("12+(2+3)").and_then(parse).unwrap(), "17");
+    fn arguments() {
+        let run = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|&arg| arg.to_owned()).collect();
+            eval(&expression(&args), &mut Evaluator::new())
+        };
+        assert_eq!(run(&["90min", "in", "s"]).unwrap(), "5400 s");
+        assert_eq!(run(&["6", "xor", "3"]).unwrap(), "5");
+        assert_eq!(run(&["2*", "(3", "+", "4)"]).unwrap(), "14");
+        assert_eq!(run(&["2", "3"]).unwrap_err(), "Error: Unexpected token: expected [operator] but found 'Number'\n2 3\n  ^");
     }
 
     #[test]
-    fn parentheses() {
-        assert_eq!(tokenize("12+(2+(3+5))+4+(((6)))").and_then(parse).unwrap(), "32");
+    fn line_editing() {
+        let mut line = LineBuffer::from_text("1+2");
+        line.left();
+        line.insert('0');
+        assert_eq!((line.text().as_str(), line.cursor()), ("1+02", 3));
+        line.home();
+        line.delete();
+        line.end();
+        line.backspace();
+        assert_eq!(line.text(), "+0");
+        line.home();
+        line.right();
+        line.kill_to_end();
+        assert_eq!(line.text(), "+");
+
+        let mut line = LineBuffer::from_text("x = sqrt(2)  ");
+        line.delete_word();
+        assert_eq!(line.text(), "x = ");
+        line.left();
+        line.kill_to_start();
+        assert_eq!((line.text().as_str(), line.cursor()), (" ", 0));
     }
 
     #[test]
-    fn multiplication() {
-        assert_eq!(tokenize("3*3").and_then(parse).unwrap(), "9");
-        assert_eq!(tokenize("3*5").and_then(parse).unwrap(), "15");
-        assert_eq!(tokenize("0*5").and_then(parse).unwrap(), "0");
-        assert_eq!(tokenize("5*4*3*2*1").and_then(parse).unwrap(), "120");
-        assert_eq!(tokenize("(5*4)*3*(2*1)").and_then(parse).unwrap(), "120");
+    fn history_search() {
+        let mut history = History::new();
+        for line in &["x = 1", "y = 2", "", "y = 2", "x + y"] {
+            history.push(line);
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.search("x", history.len()), Some(2));
+        assert_eq!(history.search("x", 2), Some(0));
+        assert_eq!(history.search("x", 0), None);
+        assert_eq!(history.search("z", history.len()), None);
+        history.push("SQRT(X)");
+        assert_eq!(history.search("sqrt(x", history.len()), Some(3));
+        assert_eq!(history.search("Y", history.len()), Some(2));
     }
 
     #[test]
-    fn division() {
-        assert_eq!(tokenize("12/4").and_then(parse).unwrap(), "3");
-        assert_eq!(tokenize("12/3").and_then(parse).unwrap(), "4");
-        assert_eq!(tokenize("5/2").and_then(parse).unwrap(), "2.5");
-        assert_eq!(tokenize("120/5/4/3/2").and_then(parse).unwrap(), "1");
-        assert_eq!(tokenize("(120/5)/4/(3/2)").and_then(parse).unwrap(), "4");
+    fn history_file() {
+        let path = env::temp_dir().join(format!("calc_history_test_{}", std::process::id()));
+        let lines: Vec<String> = (0..HISTORY_SIZE + 5).map(|i| i.to_string()).collect();
+        File::create(&path).unwrap().write_all(lines.join("\n").as_bytes()).unwrap();
+
+        let mut history = History::open(path.clone());
+        assert_eq!((history.len(), history.get(0)), (HISTORY_SIZE, "5"));
+        history.push("x = 1");
+        let saved = History::open(path.clone());
+        assert_eq!((saved.len(), saved.get(0), saved.get(HISTORY_SIZE - 1)), (HISTORY_SIZE, "6", "x = 1"));
+        let file = BufReader::new(File::open(&path).unwrap());
+        assert_eq!(file.lines().count(), HISTORY_SIZE);
+        std::fs::remove_file(&path).unwrap();
     }
 
     #[test]
-    fn exponentiation() {
-        assert_eq!(tokenize("3^2").and_then(parse).unwrap(), "9");
-        assert_eq!(tokenize("2^3^2").and_then(parse).unwrap(), "512");
-        assert_eq!(tokenize("2^(2+1)^2").and_then(parse).unwrap(), "512");
-    }
-}
-
-fn eval(input: &str) -> String {
-    match tokenize(input).and_then(parse) {
-        Ok(s) => s,
-        Err(e) => match e {
-            ParseError::InvalidNumber(s) => ["Error: Invalid number: ", s.as_str() ].concat(),
-            ParseError::UnrecognizedToken(s) => ["Error: Unrecognized token: ", s.as_str()].concat(),
-            ParseError::UnexpectedToken(found, expected) => ["Error: Unexpected token: expected [", expected, "] but found '", found.as_str(), "'"].concat(),
-            ParseError::UnexpectedEndOfInput => "Error: Unexpected end of input.".to_owned(),
-            ParseError::OtherError(s) => s,
-        }
-    }
-}
-
-fn main() {
-    let args = args();
-    let stdout = io::stdout();
-    let mut stdout = stdout.lock();
-    let mut stderr = io::stderr();
-    if args.len() > 1 {
-        let input: Vec<String> = args.skip(1).collect();
-        stdout.writeln(eval(&input.join("")).as_bytes()).try(&mut stderr);
-    } else {
-        let prompt = "[]> ".as_bytes();
-        loop {
-            stdout.write(prompt).try(&mut stderr);
-            stdout.flush().try(&mut stderr);
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)
-                       .try(&mut stderr);
-            if input.is_empty() {
-                break;
-            } else {
-                match input.trim() {
-                    "" => (),
-                    "exit" => break,
-                    s => {
-                        stdout.writeln(eval(s).as_bytes()).try(&mut stderr);
-                        stdout.flush().try(&mut stderr);
-                    },
-                }
-            }
-        }
+    fn batch_mode() {
+        let run = |input: &str| {
+            let mut out = Vec::new();
+            batch(input.as_bytes(), "exprs.txt", &mut Evaluator::new(), &mut out).map(|()| String::from_utf8(out).unwrap())
+        };
+        assert_eq!(run("1+2\n\n  # a comment\nx = 4 # the width\nx * 2\n").unwrap(), "3\n4\n8\n");
+        assert_eq!(run(":base 16\n255\nexit\n1/0\n").unwrap(), "0xff\n");
+        assert_eq!(run(":precision 2 # digits\n:sci\n:group\n2/3\n:precision\n").unwrap(), "6.67e-1\nPrecision is 2\n");
+        assert_eq!(run("1\n2 +\n3\n").unwrap_err(), "exprs.txt:2: Error: Unexpected end of input.\n2 +\n   ^");
+        assert_eq!(run("1\n:frob\n").unwrap_err(), "exprs.txt:2: Error: Unknown command: :frob");
     }
 }
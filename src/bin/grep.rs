@@ -1,118 +1,1288 @@
 #![deny(warnings)]
 extern crate extra;
+extern crate extrautils;
+extern crate termion;
 
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::io;
-use std::io::{Write, BufRead, BufReader, Stderr};
+use std::io::{Write, Read, BufRead, BufReader};
 use std::env;
+use std::fs;
 use std::fs::File;
-use std::path::Path;
-use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str;
 use std::process::exit;
 
-use extra::option::OptionalExt;
 use extra::io::WriteExt;
+use extrautils::regex::{Options, Regex, Syntax};
 
 static MAN_PAGE: &'static str = /* @MANSTART{grep} */ r#"
 NAME
     grep - print lines matching a pattern
 
 SYNOPSIS
-    grep [-h | --help] [-n --line-number] PATTERN [FILE...]
+    grep [-h | --help] [-E | -F | -G] [-iwxv] [-c | -l | -L | -o | -q] [-m NUM] [-s]
+         [-H | --no-filename] [-n --line-number] [-r | -R] [-A NUM] [-B NUM] [-C NUM]
+         [--color[=WHEN]] [--include=GLOB] [--exclude=GLOB] [--exclude-dir=GLOB]
+         (PATTERN | -e PATTERN... | -f FILE...) [FILE...]
 
 DESCRIPTION
     grep searches the named input FILEs for lines containing a match to the given PATTERN. If no
-    files are specified, grep searches the standard input. grep prints the matching lines.
+    files are specified, grep searches the standard input, or the current directory with -r. A
+    FILE of - also stands for the standard input. grep prints the matching lines, after the name
+    of the file they are in when more than one file is searched.
+
+    Several patterns may be given with -e and -f, or as one PATTERN with a pattern on each line,
+    and a line matches if any of them do. With -F all the strings are searched for together in a
+    single pass over each line, so hundreds of them cost little more than one.
+
+    Files containing NUL bytes or text which is not valid UTF-8 are taken to be binary. For those
+    grep only prints "Binary file FILE matches" when a line matches.
+
+    The file name and line number before a line of output are each followed by : for a matching
+    line, and by - for a line of context.
+
+    Single letter options may be combined, as in -in, and given their value in the same
+    argument, as in -A3. An argument of -- ends the options, so that the PATTERN and FILEs after
+    it may start with -.
+
+    PATTERN is a POSIX basic regular expression unless -E or -F is given. Matching never
+    backtracks, so it takes time in proportion to the length of each line whatever the pattern.
+    Back-references such as \1 are not supported.
 
 OPTIONS
+    -A NUM
+    --after-context=NUM
+        Print NUM lines of context after each matching line.
+
+    -B NUM
+    --before-context=NUM
+        Print NUM lines of context before each matching line.
+
+    -c
+    --count
+        Print only the number of selected lines in each file.
+
+    --color[=WHEN]
+    --colour[=WHEN]
+        Highlight matches, file names, line numbers and separators. WHEN is never, always or
+        auto, the default, which only uses color when the standard output is a terminal. Without
+        this option color is never used.
+
+    -C NUM
+    --context=NUM
+        Print NUM lines of context before and after each matching line. When context is printed,
+        groups of lines which are not next to each other are separated by a line containing --.
+
+    -e PATTERN
+    --regexp=PATTERN
+        Search for PATTERN. May be given more than once, and then no PATTERN comes before the
+        FILEs. Useful for patterns starting with -.
+
+    -E
+    --extended-regexp
+        Interpret PATTERN as an extended regular expression, where ( ) { } | + and ? are
+        operators.
+
+    -F
+    --fixed-strings
+        Interpret PATTERN as a plain string rather than a regular expression.
+
+    -f FILE
+    --file=FILE
+        Search for each of the patterns in FILE, one to a line, or in the standard input if FILE
+        is -. An empty file matches nothing. May be given more than once, and with -e.
+
+    -G
+    --basic-regexp
+        Interpret PATTERN as a basic regular expression, where only the escaped forms \( \) \{ \}
+        \| \+ and \? are operators. This is the default.
+
     -h
     --help
         Print this manual page.
 
+    --group-separator=SEP
+        Separate groups of context with SEP rather than --.
+
+    --no-group-separator
+        Do not separate groups of context at all.
+
+    -H
+    --with-filename
+        Prefix each line of output with the name of its file, even if only one file is searched.
+
+    --no-filename
+        Never prefix lines of output with the name of their file.
+
+    -i
+    --ignore-case
+        Ignore the difference between upper and lower case letters.
+
+    -L
+    --files-without-match
+        Print only the names of files with no selected lines.
+
+    -l
+    --files-with-matches
+        Print only the names of files with selected lines.
+
+    -m NUM
+    --max-count=NUM
+        Stop reading each file after NUM selected lines, and the context after the last of them.
+
     -n
     --line-number
         Prefix each line of output with the line number of the match.
+
+    -o
+    --only-matching
+        Print only the parts of selected lines which match, each on a line of its own.
+
+    -q
+    --quiet
+    --silent
+        Print nothing, and exit with status 0 as soon as a line is selected.
+
+    -r
+    --recursive
+        Search directories, and everything in them, in order of name. Symbolic links inside
+        directories are skipped, but those given as FILEs are followed.
+
+    -R
+    --dereference-recursive
+        Like -r, but follow every symbolic link.
+
+    -s
+    --no-messages
+        Do not report files which are missing or cannot be read.
+
+    -v
+    --invert-match
+        Select the lines which do not match, rather than those which do.
+
+    -w
+    --word-regexp
+        Only match whole words: a match must not have a letter, digit or underscore just before
+        or after it.
+
+    -x
+    --line-regexp
+        Only match whole lines.
+
+    --include=GLOB
+        Search only files whose name matches GLOB, which may use the wildcards *, ? and [...].
+        May be given more than once.
+
+    --exclude=GLOB
+        Skip files whose name matches GLOB. May be given more than once.
+
+    --exclude-dir=GLOB
+        When searching recursively, skip directories whose name matches GLOB. May be given more
+        than once.
+
+REGULAR EXPRESSIONS
+    .           Any character.
+    [abc] [a-z] Any of the listed characters or ranges. [^...] matches any other character.
+    [:class:]   Inside brackets, a character class: alnum, alpha, blank, cntrl, digit, graph,
+                lower, print, punct, space, upper or xdigit.
+    ^ $         The start and end of the line.
+    \< \> \b \B The start of a word, the end of a word, either, and neither.
+    \w \W \s \S A word character (letter, digit or underscore), any other, whitespace, and any
+                other.
+    *           The preceding item any number of times.
+    + ?         At least once, and at most once (\+ and \? in basic syntax).
+    {m,n}       At least m and at most n times; {m} exactly and {m,} at least (\{m,n\} in basic
+                syntax).
+    a|b         Either alternative (\| in basic syntax).
+    (...)       Group (\(...\) in basic syntax).
+
+    Where several matches start at the same place, the longest is chosen.
+
+ENVIRONMENT
+    GREP_COLORS
+        The colors used by --color, as a list of settings such as ms=01;32:fn=34 separated by
+        colons. Each value is a list of SGR parameters, which are passed to the terminal as they
+        are: 1 for bold, 4 for underline, 30 to 37 for the basic colors, 38;5;N for one of 256
+        colors and so on. An empty value leaves that part plain, and a value which is not a list
+        of numbers separated by semicolons is ignored. The settings are
+            mt  matches anywhere, setting both ms and mc
+            ms  matches in selected lines (default 01;31)
+            mc  matches in context lines (default 01;31)
+            sl  the rest of selected lines
+            cx  the rest of context lines
+            fn  file names (default 35)
+            ln  line numbers (default 32)
+            se  separators (default 36)
+
+EXIT STATUS
+    0 if any line was selected, 1 if none was, and 2 if there was an error, unless -q was given
+    and a line was selected.
 "#; /* @MANEND */
 
 #[derive(Copy, Clone)]
 struct Flags {
     line_numbers: bool,
+    syntax: Syntax,
+    with_filename: bool,
+    recursive: bool,
+    dereference: bool,
+    before_context: usize,
+    after_context: usize,
+    ignore_case: bool,
+    invert: bool,
+    whole_word: bool,
+    whole_line: bool,
+    count: bool,
+    files_with_matches: bool,
+    files_without_match: bool,
+    only_matching: bool,
+    max_count: Option<usize>,
+    quiet: bool,
+    no_messages: bool,
 }
 
 impl Flags {
     fn new() -> Flags {
-        Flags { line_numbers: false }
+        Flags {
+            line_numbers: false,
+            syntax: Syntax::Basic,
+            with_filename: false,
+            recursive: false,
+            dereference: false,
+            before_context: 0,
+            after_context: 0,
+            ignore_case: false,
+            invert: false,
+            whole_word: false,
+            whole_line: false,
+            count: false,
+            files_with_matches: false,
+            files_without_match: false,
+            only_matching: false,
+            max_count: None,
+            quiet: false,
+            no_messages: false,
+        }
+    }
+}
+
+// Ends any color or style
+const RESET: &'static str = "\x1b[m";
+
+// The escape sequences each part of the output is colored with, empty for those left plain
+struct Colors {
+    selected_match: String,
+    context_match: String,
+    selected_line: String,
+    context_line: String,
+    file_name: String,
+    line_number: String,
+    separator: String,
+}
+
+impl Colors {
+    fn new() -> Colors {
+        Colors {
+            selected_match: sgr("01;31").unwrap(),
+            context_match: sgr("01;31").unwrap(),
+            selected_line: String::new(),
+            context_line: String::new(),
+            file_name: sgr("35").unwrap(),
+            line_number: sgr("32").unwrap(),
+            separator: sgr("36").unwrap(),
+        }
+    }
+
+    // Apply settings such as `ms=01;32:fn=34` from `GREP_COLORS`, ignoring any which are not
+    // understood
+    fn configure(&mut self, settings: &str) {
+        for setting in settings.split(':') {
+            let mut parts = setting.splitn(2, '=');
+            let (name, sequence) = match (parts.next(), parts.next().and_then(sgr)) {
+                (Some(name), Some(sequence)) => (name, sequence),
+                _ => continue,
+            };
+            match name {
+                "mt" => {
+                    self.selected_match = sequence.clone();
+                    self.context_match = sequence;
+                }
+                "ms" => self.selected_match = sequence,
+                "mc" => self.context_match = sequence,
+                "sl" => self.selected_line = sequence,
+                "cx" => self.context_line = sequence,
+                "fn" => self.file_name = sequence,
+                "ln" => self.line_number = sequence,
+                "se" => self.separator = sequence,
+                _ => (),
+            }
+        }
+    }
+}
+
+// The escape sequence for SGR parameters such as `01;31`, for bold red, as `GREP_COLORS` gives
+// them. The parameters are passed on as they are, so that they mean what the terminal takes them
+// to, but anything other than numbers separated by semicolons is refused.
+fn sgr(parameters: &str) -> Option<String> {
+    if parameters.is_empty() {
+        Some(String::new())
+    } else if parameters.split(';').all(|code| !code.is_empty() && code.chars().all(|c| c.is_digit(10))) {
+        Some(["\x1b[", parameters, "m"].concat())
+    } else {
+        None
     }
 }
 
 fn main() {
-    let args = env::args().skip(1);
     let stdout = io::stdout();
     let mut stdout = stdout.lock();
     let mut stderr = io::stderr();
-    let stdin = io::stdin();
-    let stdin = stdin.lock();
-
-    let mut flags = Flags::new();
-    let mut pattern = String::new();
-    let mut files = Vec::with_capacity(args.len());
-    for arg in args {
-        if arg.starts_with("-") {
-            match arg.as_str() {
-                "-h" | "--help" => {
-                    stdout.writeln(MAN_PAGE.as_bytes()).try(&mut stderr);
-                },
-                "-n" | "--line-number" => flags.line_numbers = true,
-                _ => {
-                    stderr.write(b"Unknown option: ").try(&mut stderr);
-                    stderr.write(arg.as_bytes()).try(&mut stderr);
-                    stderr.write(b"\n").try(&mut stderr);
-                    let _ = stderr.flush();
-                    exit(1);
+
+    // Color is only used when asked for, and by default only when writing to a terminal
+    let terminal = termion::is_tty(&io::stdout()) && env::var("TERM").map(|term| term != "dumb").unwrap_or(false);
+    let settings = env::var("GREP_COLORS").ok();
+    let result = grep(env::args().skip(1), terminal, settings, &mut stdout, &mut stderr)
+        .and_then(|status| stdout.flush().map(|()| status));
+    exit(finish(result, &mut stderr));
+}
+
+// Failing to write the output is an error like any other, rather than the status for no match
+fn finish<E: Write>(result: io::Result<i32>, err: &mut E) -> i32 {
+    match result {
+        Ok(status) => status,
+        Err(e) => {
+            let _ = err.writeln(["grep: ", &e.to_string()].concat().as_bytes());
+            2
+        }
+    }
+}
+
+// What the command line asks for
+struct Arguments {
+    flags: Flags,
+    with_filename: Option<bool>,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    exclude_dir: Vec<Regex>,
+    separator: Option<String>,
+    color: bool,
+    help: bool,
+    // The patterns given with -e and -f, or `None` if there were none of those options
+    patterns: Option<Vec<String>>,
+    files: Vec<String>,
+}
+
+// Read the options and operands. `--` ends the options, so that everything after it is a pattern
+// or a file even if it starts with `-`.
+fn arguments<I: Iterator<Item = String>>(mut args: I, terminal: bool) -> Result<Arguments, String> {
+    let mut arguments = Arguments {
+        flags: Flags::new(),
+        with_filename: None,
+        include: Vec::new(),
+        exclude: Vec::new(),
+        exclude_dir: Vec::new(),
+        separator: Some("--".to_owned()),
+        color: false,
+        help: false,
+        patterns: None,
+        files: Vec::new(),
+    };
+    let mut patterns = Vec::new();
+    let mut patterns_given = false;
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            arguments.files.extend(args.by_ref());
+        } else if arg.starts_with("-") && arg != "-" {
+            let flags = &mut arguments.flags;
+            for (option, value) in split_options(&arg) {
+                match option.as_str() {
+                    "-h" | "--help" => arguments.help = true,
+                    "-A" | "--after-context" => flags.after_context = try!(number(&option, value.or_else(|| args.next()))),
+                    "-B" | "--before-context" => flags.before_context = try!(number(&option, value.or_else(|| args.next()))),
+                    "-C" | "--context" => {
+                        flags.after_context = try!(number(&option, value.or_else(|| args.next())));
+                        flags.before_context = flags.after_context;
+                    }
+                    "-c" | "--count" => flags.count = true,
+                    "--color" | "--colour" => {
+                        arguments.color = match value.as_ref().map_or("auto", |value| value.as_str()) {
+                            "always" | "yes" | "force" => true,
+                            "never" | "no" | "none" => false,
+                            "auto" | "tty" | "if-tty" => terminal,
+                            value => return Err(["Invalid argument for --color: ", value].concat()),
+                        };
+                    }
+                    "-e" | "--regexp" => {
+                        let pattern = try!(argument(&option, value.or_else(|| args.next())));
+                        patterns.extend(pattern.split('\n').map(|line| line.to_owned()));
+                        patterns_given = true;
+                    }
+                    "-E" | "--extended-regexp" => flags.syntax = Syntax::Extended,
+                    "-f" | "--file" => {
+                        let file = try!(argument(&option, value.or_else(|| args.next())));
+                        patterns.extend(try!(pattern_file(&file)));
+                        patterns_given = true;
+                    }
+                    "-F" | "--fixed-strings" => flags.syntax = Syntax::Fixed,
+                    "-G" | "--basic-regexp" => flags.syntax = Syntax::Basic,
+                    "-H" | "--with-filename" => arguments.with_filename = Some(true),
+                    "--no-filename" => arguments.with_filename = Some(false),
+                    "-i" | "--ignore-case" => flags.ignore_case = true,
+                    "-L" | "--files-without-match" => flags.files_without_match = true,
+                    "-l" | "--files-with-matches" => flags.files_with_matches = true,
+                    "-m" | "--max-count" => flags.max_count = Some(try!(number(&option, value.or_else(|| args.next())))),
+                    "-n" | "--line-number" => flags.line_numbers = true,
+                    "-o" | "--only-matching" => flags.only_matching = true,
+                    "-q" | "--quiet" | "--silent" => flags.quiet = true,
+                    "-r" | "--recursive" => flags.recursive = true,
+                    "-R" | "--dereference-recursive" => {
+                        flags.recursive = true;
+                        flags.dereference = true;
+                    }
+                    "-s" | "--no-messages" => flags.no_messages = true,
+                    "-v" | "--invert-match" => flags.invert = true,
+                    "-w" | "--word-regexp" => flags.whole_word = true,
+                    "-x" | "--line-regexp" => flags.whole_line = true,
+                    "--group-separator" => arguments.separator = Some(try!(argument(&option, value.or_else(|| args.next())))),
+                    "--no-group-separator" => arguments.separator = None,
+                    "--include" => arguments.include.push(try!(glob(&try!(argument(&option, value.or_else(|| args.next())))))),
+                    "--exclude" => arguments.exclude.push(try!(glob(&try!(argument(&option, value.or_else(|| args.next())))))),
+                    "--exclude-dir" => {
+                        arguments.exclude_dir.push(try!(glob(&try!(argument(&option, value.or_else(|| args.next()))))));
+                    }
+                    _ => return Err(["Unknown option: ", &option].concat()),
                 }
             }
-        } else if pattern.is_empty() {
-            pattern = arg.clone();
         } else {
-            match File::open(&Path::new(&arg)) {
-                Ok(f) => files.push(f),
-                Err(e) => {
-                    stderr.write(b"Error opening ").try(&mut stderr);
-                    stderr.write(arg.as_bytes()).try(&mut stderr);
-                    stderr.write(b": ").try(&mut stderr);
-                    stderr.write(e.description().as_bytes()).try(&mut stderr);
-                    stderr.write(b"\n").try(&mut stderr);
-                    let _ = stderr.flush();
-                    exit(1);
-                }
+            arguments.files.push(arg);
+        }
+    }
+    if patterns_given {
+        arguments.patterns = Some(patterns);
+    }
+    Ok(arguments)
+}
+
+// Run grep with the arguments after the program name and return the exit status, or an error if
+// the output could not be written. `terminal` is whether the output goes to a terminal, and
+// `settings` the colors from `GREP_COLORS`.
+fn grep<I, O, E>(args: I, terminal: bool, settings: Option<String>, out: &mut O, err: &mut E) -> io::Result<i32>
+        where I: Iterator<Item = String>, O: Write, E: Write {
+    let Arguments { mut flags, with_filename, include, exclude, exclude_dir, separator, color, help, patterns, mut files } =
+        match arguments(args, terminal) {
+            Ok(arguments) => arguments,
+            Err(message) => {
+                try!(err.writeln(message.as_bytes()));
+                return Ok(2);
             }
+        };
+    if help {
+        try!(out.writeln(MAN_PAGE.as_bytes()));
+    }
+
+    // Without -e or -f the pattern comes before the files
+    let patterns = match patterns {
+        Some(patterns) => patterns,
+        None if files.is_empty() => {
+            try!(err.writeln(b"You must provide a pattern"));
+            return Ok(2);
         }
+        None => files.remove(0).split('\n').map(|line| line.to_owned()).collect(),
+    };
+
+    let options = Options {
+        ignore_case: flags.ignore_case,
+        whole_word: flags.whole_word,
+        whole_line: flags.whole_line,
+    };
+    let regex = match Regex::any(&patterns, flags.syntax, options) {
+        Ok(regex) => regex,
+        Err(e) => {
+            try!(err.writeln(["Invalid pattern: ".to_owned(), e.to_string()].concat().as_bytes()));
+            return Ok(2);
+        }
+    };
+
+    // Context is only shown around whole lines of output
+    if flags.only_matching || flags.count || flags.files_with_matches || flags.files_without_match || flags.quiet {
+        flags.before_context = 0;
+        flags.after_context = 0;
     }
 
-    if pattern.is_empty() {
-        stderr.write_all(b"You must provide a pattern\n").try(&mut stderr);
-        exit(1);
+    // Name each file in the output when there could be more than one
+    let single_file = files.len() == 1 && !(flags.recursive && Path::new(&files[0]).is_dir());
+    flags.with_filename = with_filename.unwrap_or(!single_file && (files.len() > 1 || flags.recursive));
+
+    let mut search = Search::new(regex, flags);
+    search.include = include;
+    search.exclude = exclude;
+    search.exclude_dir = exclude_dir;
+    search.separator = separator;
+    if color {
+        let mut colors = Colors::new();
+        if let Some(settings) = settings {
+            colors.configure(&settings);
+        }
+        search.colors = Some(colors);
+    }
+    search.run(&files, out, err)
+}
+
+// Split an argument into the options it holds: `-in` into `-i` and `-n`, `-A3` into `-A` with the
+// value 3, and `--context=3` into `--context` with the value 3
+fn split_options(arg: &str) -> Vec<(String, Option<String>)> {
+    if arg.starts_with("--") {
+        return vec![match arg.find('=') {
+            Some(i) => (arg[..i].to_owned(), Some(arg[i + 1..].to_owned())),
+            None => (arg.to_owned(), None),
+        }];
     }
 
-    if files.is_empty() {
-        do_simple_search(BufReader::new(stdin), &pattern, &mut stdout, &mut stderr, flags);
+    let mut options = Vec::new();
+    for (i, c) in arg.char_indices().skip(1) {
+        let rest = &arg[i + c.len_utf8()..];
+        if "ABCefm".contains(c) && !rest.is_empty() {
+            options.push((format!("-{}", c), Some(rest.to_owned())));
+            break;
+        }
+        options.push((format!("-{}", c), None));
+    }
+    options
+}
+
+fn argument(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| ["Missing argument for ", option].concat())
+}
+
+// The patterns in a file, one to a line, or in the standard input for `-`
+fn pattern_file(name: &str) -> Result<Vec<String>, String> {
+    let mut contents = String::new();
+    let result = if name == "-" {
+        io::stdin().read_to_string(&mut contents)
     } else {
-        for f in files {
-            do_simple_search(BufReader::new(f), &pattern, &mut stdout, &mut stderr, flags);
+        File::open(name).and_then(|mut file| file.read_to_string(&mut contents))
+    };
+    match result {
+        Ok(_) => Ok(contents.lines().map(|line| line.to_owned()).collect()),
+        Err(e) => Err(["Error reading ", name, ": ", &e.to_string()].concat()),
+    }
+}
+
+fn number(option: &str, value: Option<String>) -> Result<usize, String> {
+    let value = try!(argument(option, value));
+    value.parse().map_err(|_| ["Invalid number for ", option, ": ", &value].concat())
+}
+
+// Compile a shell glob such as `*.rs` into an expression matching whole file names
+fn glob(pattern: &str) -> Result<Regex, String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut expression = String::from("^");
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => expression.push_str(".*"),
+            '?' => expression.push('.'),
+            '[' => {
+                // A bracket expression is copied as it is, except that it may be negated with `!`,
+                // and a `]` straight after the opening bracket is part of the set
+                let start = i + 1 + if chars.get(i + 1) == Some(&'!') { 1 } else { 0 };
+                match chars.iter().skip(start + 1).position(|&c| c == ']') {
+                    Some(length) => {
+                        let end = start + 1 + length;
+                        expression.push('[');
+                        if start > i + 1 {
+                            expression.push('^');
+                        }
+                        expression.extend(&chars[start..end]);
+                        expression.push(']');
+                        i = end;
+                    }
+                    None => expression.push_str("\\["),
+                }
+            }
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                expression.push('\\');
+                expression.push(chars[i]);
+            }
+            c => {
+                if "\\.+?(){}|^$".contains(c) {
+                    expression.push('\\');
+                }
+                expression.push(c);
+            }
         }
+        i += 1;
     }
+    expression.push('$');
+
+    Regex::new(&expression, Syntax::Extended).map_err(|e| ["Invalid glob ", pattern, ": ", &e.to_string()].concat())
+}
+
+// Everything needed to search a list of files and directories
+struct Search {
+    regex: Regex,
+    flags: Flags,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    exclude_dir: Vec<Regex>,
+    // The directories being searched, to notice symbolic links which lead back into them
+    ancestors: Vec<PathBuf>,
+    // What goes between groups of lines when context is shown, if anything
+    separator: Option<String>,
+    // Whether any lines have been printed, so that the next group needs a separator
+    printed: bool,
+    colors: Option<Colors>,
+    // Whether any line was selected
+    matched: bool,
+    // Whether any file could not be searched
+    failed: bool,
 }
 
-fn do_simple_search<T: BufRead, O: Write + WriteExt>(reader: T, pattern: &str, out: &mut O, stderr: &mut Stderr, flags: Flags) {
-    let mut line_num = 0;
-    for result in reader.lines() {
-        line_num += 1;
-        if let Ok(line) = result {
-            if line.contains(pattern) {
-                if flags.line_numbers {
-                    out.write_all((line_num.to_string() + ": ").as_bytes()).try(stderr);
+impl Search {
+    fn new(regex: Regex, flags: Flags) -> Search {
+        Search {
+            regex: regex,
+            flags: flags,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            exclude_dir: Vec::new(),
+            ancestors: Vec::new(),
+            separator: Some("--".to_owned()),
+            printed: false,
+            colors: None,
+            matched: false,
+            failed: false,
+        }
+    }
+
+    // Search the files, the standard input if there are none or the current directory when
+    // searching recursively, and return the exit status. Errors are only returned for output
+    // which could not be written.
+    fn run<O: Write, E: Write>(&mut self, files: &[String], out: &mut O, err: &mut E) -> io::Result<i32> {
+        if files.is_empty() && self.flags.recursive {
+            try!(self.directory(Path::new("."), "", out, err));
+        } else if files.is_empty() {
+            try!(self.stdin(out, err));
+        }
+        for file in files {
+            if self.finished() {
+                break;
+            } else if file == "-" {
+                try!(self.stdin(out, err));
+            } else {
+                try!(self.path(Path::new(file), file, true, out, err));
+            }
+        }
+        Ok(if self.finished() { 0 } else if self.failed { 2 } else if self.matched { 0 } else { 1 })
+    }
+
+    // With -q there is nothing more to do once a line is selected
+    fn finished(&self) -> bool {
+        self.flags.quiet && self.matched
+    }
+
+    // Report a problem with a file, unless those messages are suppressed
+    fn complain<E: Write>(&self, err: &mut E, parts: &[&str]) {
+        if !self.flags.no_messages {
+            // There is nowhere left to report a failure to write these
+            let _ = err.writeln(parts.concat().as_bytes());
+            let _ = err.flush();
+        }
+    }
+
+    fn fail<E: Write>(&mut self, err: &mut E, action: &str, name: &str, e: &io::Error) {
+        self.complain(err, &[action, name, ": ", &e.to_string()]);
+        self.failed = true;
+    }
+
+    fn stdin<O: Write, E: Write>(&mut self, out: &mut O, err: &mut E) -> io::Result<()> {
+        let stdin = io::stdin();
+        let stdin = stdin.lock();
+        match try!(self.lines(stdin, "(standard input)", out, err)) {
+            Some(selected) => self.summarize("(standard input)", selected, out),
+            None => Ok(()),
+        }
+    }
+
+    // Search a file, or a directory when searching recursively. `operand` is set for paths given
+    // on the command line, whose symbolic links are always followed.
+    fn path<O: Write, E: Write>(&mut self, path: &Path, name: &str, operand: bool, out: &mut O, err: &mut E) -> io::Result<()> {
+        let metadata = if operand || self.flags.dereference { fs::metadata(path) } else { fs::symlink_metadata(path) };
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                self.fail(err, "Error opening ", name, &e);
+                return Ok(());
+            }
+        };
+        let file_name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+
+        if metadata.is_dir() {
+            if !self.flags.recursive {
+                self.complain(err, &[name, ": Is a directory"]);
+                self.failed = true;
+            } else if operand || !self.exclude_dir.iter().any(|glob| glob.is_match(&file_name)) {
+                try!(self.directory(path, name, out, err));
+            }
+        } else if metadata.is_file() || operand {
+            // Symbolic links which are not followed, devices and sockets inside directories are
+            // skipped
+            let included = self.include.is_empty() || self.include.iter().any(|glob| glob.is_match(&file_name));
+            if included && !self.exclude.iter().any(|glob| glob.is_match(&file_name)) {
+                try!(self.file(path, name, out, err));
+            }
+        }
+        Ok(())
+    }
+
+    // Search everything in a directory, in order of name. The entries of the directory `name`
+    // are shown as `name/entry`, or just `entry` if `name` is empty.
+    fn directory<O: Write, E: Write>(&mut self, path: &Path, name: &str, out: &mut O, err: &mut E) -> io::Result<()> {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.ancestors.contains(&canonical) {
+            self.complain(err, &[name, ": Recursive directory loop"]);
+            return Ok(());
+        }
+
+        let mut entries: Vec<_> = match fs::read_dir(path) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.file_name()).collect(),
+            Err(e) => {
+                self.fail(err, "Error reading ", name, &e);
+                return Ok(());
+            }
+        };
+        entries.sort();
+
+        self.ancestors.push(canonical);
+        for entry in entries {
+            if self.finished() {
+                break;
+            }
+            let entry_name = match name {
+                "" => entry.to_string_lossy().into_owned(),
+                _ => [name.trim_right_matches('/'), "/", &entry.to_string_lossy()].concat(),
+            };
+            try!(self.path(&path.join(&entry), &entry_name, false, out, err));
+        }
+        self.ancestors.pop();
+        Ok(())
+    }
+
+    fn file<O: Write, E: Write>(&mut self, path: &Path, name: &str, out: &mut O, err: &mut E) -> io::Result<()> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                self.fail(err, "Error opening ", name, &e);
+                return Ok(());
+            }
+        };
+        match try!(self.lines(BufReader::new(file), name, out, err)) {
+            Some(selected) => self.summarize(name, selected, out),
+            None => Ok(()),
+        }
+    }
+
+    // Print the lines of a file which are selected, and the lines around them that were asked
+    // for, and return how many were selected, or `None` if the file could not be read. Files
+    // containing NUL bytes or invalid UTF-8 are taken to be binary, and only reported once as
+    // matching rather than printed.
+    fn lines<T: BufRead, O: Write, E: Write>(&mut self, mut reader: T, name: &str, out: &mut O, err: &mut E)
+            -> io::Result<Option<usize>> {
+        // With these only the number of selected lines matters, and for some only whether it is
+        // more than none
+        let counting = self.flags.count || self.flags.quiet;
+        let listing = self.flags.files_with_matches || self.flags.files_without_match || self.flags.quiet;
+
+        let mut binary = match reader.fill_buf() {
+            Ok(buffer) => buffer.contains(&0),
+            Err(e) => {
+                self.fail(err, "Error reading ", name, &e);
+                return Ok(None);
+            }
+        };
+        let mut buffer = Vec::new();
+        let mut line_num = 0;
+        let mut selected = 0;
+        // The lines since the last one printed, up to as many as are shown before a match
+        let mut before = VecDeque::with_capacity(self.flags.before_context);
+        // How many more lines to show after the last match
+        let mut after = 0;
+        let mut last_printed = None;
+        loop {
+            let limit_reached = self.flags.max_count.map_or(false, |max| selected >= max);
+            if limit_reached && after == 0 {
+                return Ok(Some(selected));
+            }
+
+            buffer.clear();
+            match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) => return Ok(Some(selected)),
+                Ok(_) => (),
+                Err(e) => {
+                    self.fail(err, "Error reading ", name, &e);
+                    return Ok(None);
+                }
+            }
+            line_num += 1;
+            if buffer.last() == Some(&b'\n') {
+                buffer.pop();
+            }
+
+            let line = match str::from_utf8(&buffer) {
+                Ok(line) if !binary && !line.contains('\0') => Cow::Borrowed(line),
+                _ => {
+                    binary = true;
+                    String::from_utf8_lossy(&buffer)
+                }
+            };
+            if limit_reached || self.regex.is_match(&line) == self.flags.invert {
+                if after > 0 && !binary {
+                    after -= 1;
+                    try!(self.print(name, line_num, &line, false, &mut last_printed, out));
+                } else if self.flags.before_context > 0 {
+                    if before.len() == self.flags.before_context {
+                        before.pop_front();
+                    }
+                    before.push_back((line_num, line.into_owned()));
+                }
+                continue;
+            }
+
+            selected += 1;
+            if listing {
+                return Ok(Some(selected));
+            } else if counting {
+                continue;
+            }
+
+            if binary {
+                try!(out.write_all(b"Binary file "));
+                try!(out.write_all(name.as_bytes()));
+                try!(out.writeln(b" matches"));
+                return Ok(Some(selected));
+            }
+            for (before_num, before_line) in before.drain(..) {
+                try!(self.print(name, before_num, &before_line, false, &mut last_printed, out));
+            }
+            if self.flags.only_matching {
+                try!(self.print_matches(name, line_num, &line, &mut last_printed, out));
+            } else {
+                try!(self.print(name, line_num, &line, true, &mut last_printed, out));
+            }
+            after = self.flags.after_context;
+        }
+    }
+
+    // Once a file has been searched, print its name or the number of lines selected if either
+    // was asked for
+    fn summarize<O: Write>(&mut self, name: &str, selected: usize, out: &mut O) -> io::Result<()> {
+        self.matched |= selected > 0;
+        if self.flags.quiet {
+            return Ok(());
+        }
+        if self.flags.files_with_matches || self.flags.files_without_match {
+            if self.flags.files_with_matches == (selected > 0) {
+                try!(self.paint(|colors| &colors.file_name, name.as_bytes(), out));
+                try!(out.writeln(b""));
+            }
+        } else if self.flags.count {
+            if self.flags.with_filename {
+                try!(self.paint(|colors| &colors.file_name, name.as_bytes(), out));
+                try!(self.paint(|colors| &colors.separator, b":", out));
+            }
+            try!(out.writeln(selected.to_string().as_bytes()));
+        }
+        Ok(())
+    }
+
+    // The non-empty matches in a line
+    fn matches(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while let Some((match_start, match_end)) = self.regex.find_at(line, start) {
+            if match_end > match_start {
+                matches.push((match_start, match_end));
+                start = match_end;
+            } else {
+                // The next match may start just after an empty one
+                match line[match_start..].chars().next() {
+                    Some(c) => start = match_start + c.len_utf8(),
+                    None => break,
                 }
-                out.writeln(line.as_bytes()).try(stderr);
             }
         }
+        matches
+    }
+
+    // Print each part of a line which matches on a line of its own
+    fn print_matches<O: Write>(&mut self, name: &str, line_num: usize, line: &str, last_printed: &mut Option<usize>,
+                               out: &mut O) -> io::Result<()> {
+        for (start, end) in self.matches(line) {
+            try!(self.prefix(name, line_num, true, last_printed, out));
+            try!(self.paint(|colors| &colors.selected_match, line[start..end].as_bytes(), out));
+            try!(out.writeln(b""));
+        }
+        Ok(())
+    }
+
+    // Print a line after its prefix, which ends in `:` if it is `selected` and in `-` if it is
+    // context, highlighting what matches when colors are on
+    fn print<O: Write>(&mut self, name: &str, line_num: usize, line: &str, selected: bool,
+                       last_printed: &mut Option<usize>, out: &mut O) -> io::Result<()> {
+        try!(self.prefix(name, line_num, selected, last_printed, out));
+        let colors = match self.colors {
+            Some(ref colors) => colors,
+            None => {
+                try!(out.writeln(line.as_bytes()));
+                return Ok(());
+            }
+        };
+
+        let (line_color, match_color) = if selected {
+            (&colors.selected_line, &colors.selected_match)
+        } else {
+            (&colors.context_line, &colors.context_match)
+        };
+        // Selected lines contain matches unless the selection is inverted, when only context does
+        let matches = if selected != self.flags.invert && !match_color.is_empty() { self.matches(line) } else { Vec::new() };
+
+        try!(out.write_all(line_color.as_bytes()));
+        let mut written = 0;
+        for (start, end) in matches {
+            try!(out.write_all(line[written..start].as_bytes()));
+            try!(out.write_all(match_color.as_bytes()));
+            try!(out.write_all(line[start..end].as_bytes()));
+            try!(out.write_all(RESET.as_bytes()));
+            try!(out.write_all(line_color.as_bytes()));
+            written = end;
+        }
+        try!(out.write_all(line[written..].as_bytes()));
+        if !line_color.is_empty() {
+            try!(out.write_all(RESET.as_bytes()));
+        }
+        try!(out.writeln(b""));
+        Ok(())
+    }
+
+    // Print what comes before a line: a separator if it starts a new group of lines when context
+    // is shown, then the file name and line number if they were asked for, each followed by `:`
+    // for selected lines and by `-` for context
+    fn prefix<O: Write>(&mut self, name: &str, line_num: usize, selected: bool, last_printed: &mut Option<usize>,
+                        out: &mut O) -> io::Result<()> {
+        let context = self.flags.before_context > 0 || self.flags.after_context > 0;
+        let gap = match *last_printed {
+            Some(last) => line_num > last + 1,
+            None => self.printed,
+        };
+        if let Some(ref separator) = self.separator {
+            if context && gap {
+                try!(self.paint(|colors| &colors.separator, separator.as_bytes(), out));
+                try!(out.writeln(b""));
+            }
+        }
+        *last_printed = Some(line_num);
+        self.printed = true;
+
+        let delimiter: &[u8] = if selected { b":" } else { b"-" };
+        if self.flags.with_filename {
+            try!(self.paint(|colors| &colors.file_name, name.as_bytes(), out));
+            try!(self.paint(|colors| &colors.separator, delimiter, out));
+        }
+        if self.flags.line_numbers {
+            try!(self.paint(|colors| &colors.line_number, line_num.to_string().as_bytes(), out));
+            try!(self.paint(|colors| &colors.separator, delimiter, out));
+        }
+        Ok(())
+    }
+
+    // Write text in one of the colors, if colors are on and that one is set
+    fn paint<O: Write, F: Fn(&Colors) -> &String>(&self, color: F, text: &[u8], out: &mut O) -> io::Result<()> {
+        match self.colors {
+            Some(ref colors) if !color(colors).is_empty() => {
+                try!(out.write_all(color(colors).as_bytes()));
+                try!(out.write_all(text));
+                out.write_all(RESET.as_bytes())
+            }
+            _ => out.write_all(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    // A fresh directory of files for one test, named after it
+    fn tree(test: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let root = env::temp_dir().join(["grep-test-", test].concat());
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        for &(name, contents) in files {
+            let path = root.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(&path).unwrap().write_all(contents).unwrap();
+        }
+        root
+    }
+
+    // Run a search over files in `root`, and return the exit status and what was written, with
+    // `root` left out of the names of files
+    fn run(mut search: Search, root: &Path, files: &[&str]) -> (i32, String, String) {
+        let files = paths(root, files);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let status = search.run(&files, &mut out, &mut err).unwrap();
+        (status, strip(root, out), strip(root, err))
+    }
+
+    // Run grep as if from the command line, with `files` in `root` after the other arguments
+    fn command(args: &[&str], root: &Path, files: &[&str]) -> (i32, String, String) {
+        let args = args.iter().map(|&arg| arg.to_owned()).chain(paths(root, files));
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let status = grep(args, false, None, &mut out, &mut err).unwrap();
+        (status, strip(root, out), strip(root, err))
+    }
+
+    fn paths(root: &Path, files: &[&str]) -> Vec<String> {
+        files.iter().map(|file| root.join(file).to_string_lossy().into_owned()).collect()
+    }
+
+    fn strip(root: &Path, output: Vec<u8>) -> String {
+        String::from_utf8(output).unwrap().replace(&[&root.to_string_lossy(), "/"].concat(), "")
+    }
+
+    fn search(pattern: &str, flags: Flags) -> Search {
+        Search::new(Regex::new(pattern, flags.syntax).unwrap(), flags)
+    }
+
+    // The output of searching the lines `1` to `12`
+    fn numbers(search: &mut Search) -> String {
+        let input: String = (1..13).map(|n| format!("{}\n", n)).collect();
+        let mut out = Vec::new();
+        search.lines(input.as_bytes(), "nums", &mut out, &mut Vec::new()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn context() {
+        let context = |before: usize, after: usize| Flags { before_context: before, after_context: after, ..Flags::new() };
+        assert_eq!(numbers(&mut search("^[27]$", context(1, 1))), "1\n2\n3\n--\n6\n7\n8\n");
+        assert_eq!(numbers(&mut search("^[1-3]$", context(0, 0))), "1\n2\n3\n");
+        assert_eq!(numbers(&mut search("^12$", context(2, 3))), "10\n11\n12\n");
+
+        // Groups which overlap or touch are joined
+        assert_eq!(numbers(&mut search("^[35]$", context(1, 1))), "2\n3\n4\n5\n6\n");
+        assert_eq!(numbers(&mut search("^[36]$", context(0, 2))), "3\n4\n5\n6\n7\n8\n");
+        assert_eq!(numbers(&mut search("^[36]$", context(1, 1))), "2\n3\n4\n5\n6\n7\n");
+        assert_eq!(numbers(&mut search("^[37]$", context(1, 1))), "2\n3\n4\n--\n6\n7\n8\n");
+
+        // Context stops at the end of the last group allowed by -m, and matches in it are context
+        let limited = Flags { max_count: Some(1), line_numbers: true, ..context(0, 2) };
+        assert_eq!(numbers(&mut search("^[23]$", limited)), "2:2\n3-3\n4-4\n");
+        let limited = Flags { max_count: Some(2), ..context(1, 1) };
+        assert_eq!(numbers(&mut search("^[279]$", limited)), "1\n2\n3\n--\n6\n7\n8\n");
+    }
+
+    #[test]
+    fn separators() {
+        let flags = Flags { line_numbers: true, with_filename: true, before_context: 1, ..Flags::new() };
+        assert_eq!(numbers(&mut search("^[49]$", flags)), "nums-3-3\nnums:4:4\n--\nnums-8-8\nnums:9:9\n");
+
+        let mut custom = search("^[49]$", flags);
+        custom.separator = Some("~~".to_owned());
+        assert_eq!(numbers(&mut custom), "nums-3-3\nnums:4:4\n~~\nnums-8-8\nnums:9:9\n");
+        let mut none = search("^[49]$", flags);
+        none.separator = None;
+        assert_eq!(numbers(&mut none), "nums-3-3\nnums:4:4\nnums-8-8\nnums:9:9\n");
+
+        // Groups in different files are separated too, but not the first one
+        let mut files = search("^1$", Flags { after_context: 1, ..Flags::new() });
+        assert_eq!(numbers(&mut files), "1\n2\n");
+        assert_eq!(numbers(&mut files), "--\n1\n2\n");
+
+        // Inverted matches are still selected lines
+        let inverted = Flags { invert: true, line_numbers: true, after_context: 1, ..Flags::new() };
+        assert_eq!(numbers(&mut search("^([3-9]|1[0-1])$", Flags { syntax: Syntax::Extended, ..inverted })),
+                   "1:1\n2:2\n3-3\n--\n12:12\n");
+    }
+
+    #[test]
+    fn colors() {
+        let mut colors = Colors::new();
+        assert_eq!((colors.selected_match.as_str(), colors.file_name.as_str()), ("\x1b[01;31m", "\x1b[35m"));
+        colors.configure("ms=1;32:fn=:ln=38;5;208:se=22;24;27:cx=2:sl=100;97");
+        assert_eq!(colors.selected_match, "\x1b[1;32m");
+        assert_eq!(colors.context_match, "\x1b[01;31m");
+        assert_eq!(colors.file_name, "");
+        assert_eq!(colors.line_number, "\x1b[38;5;208m");
+        assert_eq!(colors.separator, "\x1b[22;24;27m");
+        assert_eq!(colors.context_line, "\x1b[2m");
+        assert_eq!(colors.selected_line, "\x1b[100;97m");
+
+        // Settings which are not understood are skipped, and the rest still apply
+        colors.configure("mt=33:ms=bold:ln=1;;4:fn=\x1b[5m:zz=1:ne:se");
+        assert_eq!((colors.selected_match.as_str(), colors.context_match.as_str()), ("\x1b[33m", "\x1b[33m"));
+        assert_eq!(colors.line_number, "\x1b[38;5;208m");
+        assert_eq!(colors.file_name, "");
+        assert_eq!(colors.separator, "\x1b[22;24;27m");
+
+        let root = tree("colors", &[("a.txt", b"say hello\n")]);
+        let mut search = search("hel*", Flags { line_numbers: true, ..Flags::new() });
+        search.colors = Some(Colors::new());
+        assert_eq!(run(search, &root, &["a.txt"]).1,
+                   "\x1b[32m1\x1b[m\x1b[36m:\x1b[msay \x1b[01;31mhell\x1b[mo\n");
+    }
+
+    #[test]
+    fn end_of_options() {
+        let root = tree("end_of_options", &[("dashes.txt", b"-x\n--\nx\n"), ("-v", b"-x\n")]);
+        assert_eq!(command(&["--", "-x"], &root, &["dashes.txt"]), (0, "-x\n".to_owned(), String::new()));
+        assert_eq!(command(&["-c", "--", "--"], &root, &["dashes.txt"]), (0, "1\n".to_owned(), String::new()));
+        assert_eq!(command(&["-e", "--"], &root, &["dashes.txt"]), (0, "--\n".to_owned(), String::new()));
+        assert_eq!(command(&["-n", "x", "--"], &root, &["-v", "dashes.txt"]),
+                   (0, "-v:1:-x\ndashes.txt:1:-x\ndashes.txt:3:x\n".to_owned(), String::new()));
+
+        let operands = arguments(vec!["-e", "a", "--", "-i", "--", "-"].into_iter().map(|arg| arg.to_owned()), false).unwrap();
+        assert!(!operands.flags.ignore_case);
+        assert_eq!(operands.patterns, Some(vec!["a".to_owned()]));
+        assert_eq!(operands.files, ["-i", "--", "-"]);
+    }
+
+    #[test]
+    fn exit_status() {
+        let root = tree("exit_status", &[("a.txt", b"apple\nbanana\n"), ("b.txt", b"cherry\n")]);
+        assert_eq!(command(&["apple"], &root, &["a.txt"]).0, 0);
+        assert_eq!(command(&["grape"], &root, &["a.txt", "b.txt"]).0, 1);
+        assert_eq!(command(&["-v", "apple"], &root, &["a.txt"]).0, 0);
+        assert_eq!(command(&["-v", "a"], &root, &["a.txt"]).0, 1);
+
+        // Errors win over matches, unless -q was given and a line was selected
+        assert_eq!(command(&["apple"], &root, &["missing.txt", "a.txt"]),
+                   (2, "a.txt:apple\n".to_owned(), "Error opening missing.txt: No such file or directory (os error 2)\n".to_owned()));
+        assert_eq!(command(&["-s", "apple"], &root, &["missing.txt", "a.txt"]), (2, "a.txt:apple\n".to_owned(), String::new()));
+        assert_eq!(command(&["-q", "apple"], &root, &["missing.txt", "a.txt"]).0, 0);
+        assert_eq!(command(&["-q", "grape"], &root, &["missing.txt", "a.txt"]).0, 2);
+
+        // Usage errors
+        for args in &[&["-A"][..], &["-m", "x", "a"], &["--frobnicate", "a"], &["-E", "(a"], &["--color=rainbow", "a"]] {
+            let (status, out, err) = command(args, &root, &["a.txt"]);
+            assert_eq!((status, out.as_str()), (2, ""), "for {:?}", args);
+            assert!(!err.is_empty(), "for {:?}", args);
+        }
+        assert_eq!(command(&["-m", "x", "a"], &root, &[]).2, "Invalid number for -m: x\n");
+        assert_eq!(command(&["-c"], &root, &[]), (2, String::new(), "You must provide a pattern\n".to_owned()));
+
+        // Output which cannot be written, either at once or when it is flushed at the end
+        let args = || vec!["apple".to_owned(), root.join("a.txt").to_string_lossy().into_owned()].into_iter();
+        let mut buffered = io::BufWriter::new(&mut [0u8; 0][..]);
+        let results = vec![grep(args(), false, None, &mut &mut [0u8; 0][..], &mut Vec::new()),
+                           grep(args(), false, None, &mut buffered, &mut Vec::new()).and_then(|status| {
+                               buffered.flush().map(|()| status)
+                           })];
+        for result in results {
+            let mut err = Vec::new();
+            assert_eq!(finish(result, &mut err), 2);
+            assert!(String::from_utf8(err).unwrap().starts_with("grep: "));
+        }
+        assert_eq!(finish(Ok(1), &mut Vec::new()), 1);
+    }
+
+    #[test]
+    fn output_modes() {
+        let root = tree("output_modes", &[("a.txt", b"one apple, two apples\nbanana\napple pie\n"), ("b.txt", b"cherry\n")]);
+        let grep = |args: &[&str]| command(args, &root, &["a.txt", "b.txt"]).1;
+        assert_eq!(grep(&["-c", "apple"]), "a.txt:2\nb.txt:0\n");
+        assert_eq!(grep(&["-c", "-v", "apple"]), "a.txt:1\nb.txt:1\n");
+        assert_eq!(grep(&["-l", "an"]), "a.txt\n");
+        assert_eq!(grep(&["-L", "an"]), "b.txt\n");
+        assert_eq!(grep(&["-o", "-n", "apples*"]), "a.txt:1:apple\na.txt:1:apples\na.txt:3:apple\n");
+        assert_eq!(grep(&["-o", "-i", "-e", "A", "-e", "an"]), "a.txt:a\na.txt:a\na.txt:an\na.txt:an\na.txt:a\na.txt:a\n");
+        assert_eq!(grep(&["-q", "an"]), "");
+        assert_eq!(grep(&["-m", "1", "-n", "a"]), "a.txt:1:one apple, two apples\n");
+        assert_eq!(grep(&["-m1", "-c", "a"]), "a.txt:1\nb.txt:0\n");
+        assert_eq!(grep(&["-m", "0", "a"]), "");
+        assert_eq!(grep(&["--no-filename", "-x", "banana"]), "banana\n");
+        assert_eq!(command(&["-H", "cherry"], &root, &["b.txt"]).1, "b.txt:cherry\n");
+        assert_eq!(command(&["-A", "1", "-o", "banana"], &root, &["a.txt"]).1, "banana\n");
+    }
+
+    #[test]
+    fn recursion() {
+        let root = tree("recursion", &[("b.txt", b"match b\n"), ("a/z.txt", b"match z\nno\n"), ("a/c/d.txt", b"match d\n"),
+                                       ("e.txt", b"nothing\n")]);
+        let recursive = Flags { recursive: true, with_filename: true, ..Flags::new() };
+        assert_eq!(run(search("match", recursive), &root, &["."]),
+                   (0, "./a/c/d.txt:match d\n./a/z.txt:match z\n./b.txt:match b\n".to_owned(), String::new()));
+        assert_eq!(run(search("match", recursive), &root, &["a/", "e.txt"]),
+                   (0, "a/c/d.txt:match d\na/z.txt:match z\n".to_owned(), String::new()));
+        assert_eq!(run(search("absent", recursive), &root, &["a"]), (1, String::new(), String::new()));
+
+        let (status, out, err) = run(search("match", Flags::new()), &root, &["a", "b.txt"]);
+        assert_eq!((status, out.as_str(), err.as_str()), (2, "match b\n", "a: Is a directory\n"));
+    }
+
+    #[test]
+    fn globs() {
+        let root = tree("globs", &[("main.rs", b"x\n"), ("lib.rs.bak", b"x\n"), ("notes.md", b"x\n"),
+                                   ("target/out.rs", b"x\n"), ("src/target.rs", b"x\n"), ("src/[1].rs", b"x\n")]);
+        let flags = Flags { recursive: true, files_with_matches: true, ..Flags::new() };
+        let glob = |pattern: &str| glob(pattern).unwrap();
+
+        let mut rust = search("x", flags);
+        rust.include = vec![glob("*.rs")];
+        assert_eq!(run(rust, &root, &["."]).1, "./main.rs\n./src/[1].rs\n./src/target.rs\n./target/out.rs\n");
+
+        let mut skipped = search("x", flags);
+        skipped.exclude = vec![glob("*.md"), glob("*.ba?"), glob("\\[[0-9]].rs")];
+        skipped.exclude_dir = vec![glob("targ*")];
+        assert_eq!(run(skipped, &root, &["."]).1, "./main.rs\n./src/target.rs\n");
+
+        // Directories named on the command line are searched even if they are excluded
+        let mut named = search("x", flags);
+        named.exclude_dir = vec![glob("target")];
+        assert_eq!(run(named, &root, &["target"]).1, "target/out.rs\n");
+
+        let mut negated = search("x", flags);
+        negated.include = vec![glob("[!m]*")];
+        assert_eq!(run(negated, &root, &["src", "main.rs", "notes.md"]).1, "src/[1].rs\nsrc/target.rs\nnotes.md\n");
+    }
+
+    #[test]
+    fn symbolic_links() {
+        let root = tree("symbolic_links", &[("dir/real.txt", b"x\n"), ("other/linked.txt", b"x\n")]);
+        symlink(root.join("dir/real.txt"), root.join("dir/file-link")).unwrap();
+        symlink(root.join("other"), root.join("dir/dir-link")).unwrap();
+        symlink(root.join("dir"), root.join("dir/loop")).unwrap();
+        let flags = Flags { recursive: true, files_with_matches: true, ..Flags::new() };
+
+        // Links inside directories are skipped by -r, but followed when named
+        assert_eq!(run(search("x", flags), &root, &["dir"]), (0, "dir/real.txt\n".to_owned(), String::new()));
+        assert_eq!(run(search("x", flags), &root, &["dir/file-link", "dir/dir-link"]),
+                   (0, "dir/file-link\ndir/dir-link/linked.txt\n".to_owned(), String::new()));
+
+        let dereference = Flags { dereference: true, ..flags };
+        assert_eq!(run(search("x", dereference), &root, &["dir"]),
+                   (0, "dir/dir-link/linked.txt\ndir/file-link\ndir/real.txt\n".to_owned(),
+                    "dir/loop: Recursive directory loop\n".to_owned()));
+    }
+
+    #[test]
+    fn binary_files() {
+        // Only the start of a file is checked for NUL bytes before searching it
+        let late = [&b"match\n"[..], &[b'.'; 10000], b"\nthen\0\n"].concat();
+        let root = tree("binary_files", &[("nul.bin", b"text\0more\nmatch\n"), ("latin1.txt", b"caf\xe9\nmatch\n"),
+                                          ("late.bin", &late), ("plain.txt", b"match\n")]);
+        let flags = Flags { with_filename: true, ..Flags::new() };
+        assert_eq!(run(search("match", flags), &root, &["nul.bin", "latin1.txt", "plain.txt"]).1,
+                   "Binary file nul.bin matches\nBinary file latin1.txt matches\nplain.txt:match\n");
+        assert_eq!(run(search("caf", flags), &root, &["latin1.txt"]).1, "Binary file latin1.txt matches\n");
+        assert_eq!(run(search("match", flags), &root, &["late.bin"]).1, "late.bin:match\n");
+        assert_eq!(run(search("then", flags), &root, &["late.bin"]).1, "Binary file late.bin matches\n");
+
+        let counting = Flags { count: true, ..flags };
+        assert_eq!(run(search("t", counting), &root, &["nul.bin"]).1, "nul.bin:2\n");
     }
 }
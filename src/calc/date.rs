@@ -0,0 +1,183 @@
+//! Calendar dates, times of day and durations. Dates are counted in seconds since the Unix epoch
+//! in UTC, and read and written in a fixed offset from UTC.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+// Dates are limited to the years ISO-8601 writes with four digits, from 0000-01-01 up to but not
+// including 10000-01-01
+const FIRST_SECOND: i64 = -62167219200;
+const END_SECOND: i64 = 253402300800;
+
+// The number of days from 1970-01-01 to a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The year, month and day of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    (year_of_era + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+// Split a number of seconds into whole days and the seconds into the last day, rounding towards
+// the past so that times before 1970 fall on the right day
+fn split_days(seconds: i64) -> (i64, i64) {
+    let days = if seconds >= 0 { seconds / SECONDS_PER_DAY } else { (seconds + 1) / SECONDS_PER_DAY - 1 };
+    (days, seconds - days * SECONDS_PER_DAY)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    days_from_civil(if month == 12 { year + 1 } else { year }, month % 12 + 1, 1) - days_from_civil(year, month, 1)
+}
+
+// Split a run of numbers such as `17:45:30` at each separator
+fn fields(s: &str, separator: char) -> Option<Vec<i64>> {
+    s.split(separator).map(|field| field.parse::<i64>().ok()).collect()
+}
+
+/// Read a time of day such as `17:45` or `17:45:30` as a number of seconds since midnight.
+pub fn parse_clock(literal: &str) -> Option<i64> {
+    let fields = match fields(literal, ':') {
+        Some(fields) => fields,
+        None => return None,
+    };
+    let (hours, minutes, seconds) = match fields.len() {
+        2 => (fields[0], fields[1], 0),
+        3 => (fields[0], fields[1], fields[2]),
+        _ => return None,
+    };
+    if hours < 24 && minutes < 60 && seconds < 60 {
+        Some(hours * 3600 + minutes * 60 + seconds)
+    } else {
+        None
+    }
+}
+
+/// Read a date such as `2026-10-17`, or a date and time such as `2026-10-17T09:30`, in the time
+/// zone `offset` seconds east of UTC, as a number of seconds since the Unix epoch.
+pub fn parse_date(literal: &str, offset: i64) -> Option<i64> {
+    let (date, clock) = match literal.find('T') {
+        Some(i) => (&literal[..i], match parse_clock(&literal[i + 1..]) {
+            Some(seconds) => seconds,
+            None => return None,
+        }),
+        None => (literal, 0),
+    };
+    match fields(date, '-') {
+        Some(ref fields) if fields.len() == 3 => {
+            let (year, month, day) = (fields[0], fields[1], fields[2]);
+            if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+                return None;
+            }
+            let seconds = days_from_civil(year, month, day) * SECONDS_PER_DAY + clock - offset;
+            if in_range(seconds as f64) { Some(seconds) } else { None }
+        }
+        _ => None,
+    }
+}
+
+/// Whether a number of seconds since the Unix epoch is a date that can be written.
+pub fn in_range(seconds: f64) -> bool {
+    seconds >= FIRST_SECOND as f64 && seconds < END_SECOND as f64
+}
+
+/// The current time, in seconds since the Unix epoch.
+pub fn now() -> f64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9,
+        Err(before) => -(before.duration().as_secs() as f64),
+    }
+}
+
+/// Midnight at the start of the current day in the time zone `offset` seconds east of UTC.
+pub fn today(offset: i64) -> i64 {
+    let (days, _) = split_days(now().floor() as i64 + offset);
+    days * SECONDS_PER_DAY - offset
+}
+
+/// Read an offset from UTC such as `+02:00`, `-0530` or `Z`, as a number of seconds.
+pub fn parse_offset(s: &str) -> Option<i64> {
+    if s == "Z" || s == "UTC" {
+        return Some(0);
+    }
+    let sign = match s.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return None,
+    };
+    let digits: String = s[1..].chars().filter(|&c| c != ':').collect();
+    if !digits.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (&digits[..], "0"),
+        4 => (&digits[..2], &digits[2..]),
+        _ => return None,
+    };
+    match (hours.parse::<i64>(), minutes.parse::<i64>()) {
+        (Ok(hours), Ok(minutes)) if hours <= 18 && minutes < 60 => Some(sign * (hours * 3600 + minutes * 60)),
+        _ => None,
+    }
+}
+
+/// An offset from UTC in the form `+02:00`, or `Z` for UTC itself.
+pub fn format_offset(offset: i64) -> String {
+    if offset == 0 {
+        return "Z".to_owned();
+    }
+    let sign = if offset < 0 { "-" } else { "+" };
+    format!("{}{:02}:{:02}", sign, offset.abs() / 3600, offset.abs() % 3600 / 60)
+}
+
+/// Write a point in time as an ISO-8601 date in the time zone `offset` seconds east of UTC, with
+/// the time of day and offset unless it is midnight. Dates out of range are written as seconds.
+pub fn format_date(seconds: f64, offset: i64) -> String {
+    let local = match (seconds.floor() as i64).checked_add(offset) {
+        Some(local) if in_range(seconds) => local,
+        _ => return seconds.to_string(),
+    };
+    let (days, clock) = split_days(local);
+    let (year, month, day) = civil_from_days(days);
+    let date = format!("{:04}-{:02}-{:02}", year, month, day);
+    match clock {
+        0 => date,
+        clock => {
+            format!("{}T{:02}:{:02}:{:02}{}", date, clock / 3600, clock % 3600 / 60, clock % 60, format_offset(offset))
+        }
+    }
+}
+
+/// Write a number of seconds as days, hours, minutes and seconds, such as `8h 35min`.
+pub fn format_duration(seconds: f64) -> String {
+    if !seconds.is_finite() {
+        return [&seconds.to_string(), "s"].concat();
+    }
+    let whole = seconds.abs().trunc() as u64;
+    let fraction = seconds.abs().fract();
+    let mut parts: Vec<String> = [(whole / 86400, "d"), (whole % 86400 / 3600, "h"), (whole % 3600 / 60, "min")]
+        .iter()
+        .filter(|&&(n, _)| n > 0)
+        .map(|&(n, unit)| format!("{}{}", n, unit))
+        .collect();
+    if fraction > 0.0 {
+        let s = format!("{:.3}", (whole % 60) as f64 + fraction);
+        parts.push([s.trim_right_matches('0').trim_right_matches('.'), "s"].concat());
+    } else if whole % 60 > 0 || parts.is_empty() {
+        parts.push(format!("{}s", whole % 60));
+    }
+    [if seconds < 0.0 { "-" } else { "" }, &parts.join(" ")].concat()
+}
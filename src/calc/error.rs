@@ -0,0 +1,67 @@
+//! Errors, and the part of the input they refer to.
+
+use std::fmt;
+
+use super::token::Span;
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(kind: ErrorKind, span: Span) -> Self {
+        ParseError {
+            kind: kind,
+            span: span,
+        }
+    }
+}
+
+#[derive(Debug,  Clone)]
+pub enum ErrorKind {
+    InvalidNumber(String),
+    UnrecognizedToken(String),
+    UnexpectedToken(String, &'static str),
+    UnexpectedEndOfInput,
+    UnknownVariable(String),
+    UnknownFunction(String),
+    WrongArgumentCount(String, usize, usize),
+    IntegerRequired(&'static str),
+    UnknownUnit(String),
+    IncompatibleUnits(String, String),
+    RecursionLimit(String),
+    TypeMismatch(&'static str, &'static str),
+    OtherError(String),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::InvalidNumber(ref s) => write!(f, "Error: Invalid number: {}", s),
+            ErrorKind::UnrecognizedToken(ref s) => write!(f, "Error: Unrecognized token: {}", s),
+            ErrorKind::UnexpectedToken(ref found, expected) => {
+                write!(f, "Error: Unexpected token: expected [{}] but found '{}'", expected, found)
+            }
+            ErrorKind::UnexpectedEndOfInput => f.write_str("Error: Unexpected end of input."),
+            ErrorKind::UnknownVariable(ref s) => write!(f, "Error: Unknown variable: {}", s),
+            ErrorKind::UnknownFunction(ref s) => write!(f, "Error: Unknown function: {}", s),
+            ErrorKind::WrongArgumentCount(ref name, expected, found) => {
+                write!(f, "Error: {}() takes {} argument(s) but {} were given", name, expected, found)
+            }
+            ErrorKind::IntegerRequired(op) => write!(f, "Error: Operator {} requires integer operands", op),
+            ErrorKind::UnknownUnit(ref s) => write!(f, "Error: Unknown unit: {}", s),
+            ErrorKind::IncompatibleUnits(ref a, ref b) => write!(f, "Error: Incompatible units: {} and {}", a, b),
+            ErrorKind::TypeMismatch(expected, found) => write!(f, "Error: Expected a {} but found a {}", expected, found),
+            ErrorKind::RecursionLimit(ref s) => write!(f, "Error: Maximum recursion depth exceeded in {}()", s),
+            ErrorKind::OtherError(ref s) => write!(f, "Error: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
@@ -0,0 +1,441 @@
+//! Evaluating expression trees against the variables and functions of a session.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use super::date;
+use super::error::{ErrorKind, ParseError};
+use super::format::Format;
+use super::function::{find_function, CONSTANTS};
+use super::number::{BigInt, Complex, Rational};
+use super::parser::{BinaryOperator, Expr, ExprKind, Parser, Statement, UnaryOperator};
+use super::token::{Span, SpannedToken};
+use super::unit::{duration_unit, find_unit};
+use super::value::Value;
+
+/// A function defined during a session with `name(params) = body`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserFunction {
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+// How deeply user defined functions may call each other before evaluation is abandoned
+const MAX_CALL_DEPTH: usize = 256;
+
+/// The variables and functions known to a calculator session, including the implicit `ans`
+/// register, and the format results are written in.
+#[derive(Clone, Debug)]
+pub struct Environment {
+    variables: HashMap<String, Value>,
+    functions: HashMap<String, UserFunction>,
+    exact: bool,
+    pub format: Format,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment {
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            exact: false,
+            format: Format::new(),
+        }
+    }
+
+    /// An environment where number literals are read as exact rationals.
+    pub fn exact() -> Self {
+        Environment {
+            exact: true,
+            ..Environment::new()
+        }
+    }
+
+    pub fn set_exact(&mut self, exact: bool) {
+        self.exact = exact;
+    }
+
+    /// Look up a variable, falling back to the built-in constants, the imaginary unit `i`, `true`
+    /// and `false`, and the current time `now` and date `today`.
+    pub fn get(&self, name: &str) -> Result<Value, ErrorKind> {
+        self.variables.get(name)
+                      .cloned()
+                      .or_else(|| CONSTANTS.iter().find(|c| c.0 == name).map(|c| Value::Float(c.1)))
+                      .or_else(|| match name {
+                          "i" => Some(Value::Complex(Complex::new(0.0, 1.0))),
+                          "true" => Some(Value::Bool(true)),
+                          "false" => Some(Value::Bool(false)),
+                          "now" => Some(Value::Date(Box::new(Value::Float(date::now())))),
+                          "today" => Some(Value::Date(Box::new(self.seconds(date::today(self.format.offset))))),
+                          _ => None,
+                      })
+                      .ok_or_else(|| ErrorKind::UnknownVariable(name.to_owned()))
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.variables.insert(name.to_owned(), value);
+    }
+
+    /// Define a function, replacing any previous definition. Built-in functions cannot be
+    /// redefined.
+    pub fn define(&mut self, name: &str, function: UserFunction) -> Result<(), ErrorKind> {
+        if find_function(name).is_some() {
+            return Err(ErrorKind::OtherError(["Cannot redefine built-in function ", name].concat()));
+        }
+        self.functions.insert(name.to_owned(), function);
+        Ok(())
+    }
+
+    pub fn function(&self, name: &str) -> Option<&UserFunction> {
+        self.functions.get(name)
+    }
+
+    /// The variables, sorted by name.
+    pub fn variables(&self) -> Vec<(&str, &Value)> {
+        let mut variables: Vec<_> = self.variables.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        variables
+    }
+
+    /// The user defined functions, sorted by name.
+    pub fn functions(&self) -> Vec<(&str, &UserFunction)> {
+        let mut functions: Vec<_> = self.functions.iter().map(|(k, f)| (k.as_str(), f)).collect();
+        functions.sort_by(|a, b| a.0.cmp(b.0));
+        functions
+    }
+
+    // A whole number of seconds in the domain of the current mode
+    fn seconds(&self, seconds: i64) -> Value {
+        let magnitude = BigInt::from_u64(seconds.abs() as u64);
+        let magnitude = if seconds < 0 { magnitude.neg() } else { magnitude };
+        if self.exact { Value::Exact(Rational::from_integer(magnitude)) } else { Value::Float(seconds as f64) }
+    }
+
+    /// Read a number literal in the domain of the current mode. Dates such as `2026-10-17` and
+    /// times of day such as `17:45`, which is a duration since midnight, are also accepted.
+    pub fn number(&self, literal: &str) -> Result<Value, ErrorKind> {
+        let invalid = || ErrorKind::InvalidNumber(literal.to_owned());
+        if literal.contains('-') {
+            let seconds = try!(date::parse_date(literal, self.format.offset).ok_or_else(&invalid));
+            return Ok(Value::Date(Box::new(self.seconds(seconds))));
+        }
+        if literal.contains(':') {
+            let seconds = try!(date::parse_clock(literal).ok_or_else(&invalid));
+            return Ok(Value::Quantity(Box::new(self.seconds(seconds)), duration_unit()));
+        }
+        let digits: String = literal.chars().filter(|&c| c != '_').collect();
+        let radix = match digits.get(..2) {
+            Some("0x") | Some("0X") => 16,
+            Some("0o") | Some("0O") => 8,
+            Some("0b") | Some("0B") => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            let exact = self.exact;
+            return BigInt::parse_radix(&digits[2..], radix)
+                .map(|n| if exact { Value::Exact(Rational::from_integer(n)) } else { Value::Float(n.to_f64()) })
+                .ok_or_else(|| ErrorKind::InvalidNumber(literal.to_owned()));
+        }
+
+        let literal = digits.as_str();
+        if self.exact {
+            Rational::parse_decimal(literal).map(Value::Exact)
+        } else {
+            literal.parse::<f64>().ok().map(Value::Float)
+        }.ok_or_else(|| ErrorKind::InvalidNumber(literal.to_owned()))
+    }
+}
+
+// Attach the location of the expression being evaluated to an error
+fn at<T>(result: Result<T, ErrorKind>, span: Span) -> Result<T, ParseError> {
+    result.map_err(|kind| ParseError::new(kind, span))
+}
+
+/// The names visible while evaluating an expression: the parameters of the function being
+/// called, if any, and then the variables of the session.
+pub struct Scope<'a> {
+    env: &'a Environment,
+    params: Vec<(&'a str, Value)>,
+    depth: usize,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(env: &'a Environment) -> Self {
+        Scope {
+            env: env,
+            params: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    /// Whether `name` is a parameter or a variable of the session, rather than a built in name.
+    fn defines(&self, name: &str) -> bool {
+        self.params.iter().any(|p| p.0 == name) || self.env.variables.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Value, ErrorKind> {
+        match self.params.iter().find(|p| p.0 == name) {
+            Some(&(_, ref value)) => Ok(value.clone()),
+            None => self.env.get(name),
+        }
+    }
+}
+
+/// Evaluate an expression tree.
+pub fn evaluate(expr: &Expr, env: &Environment) -> Result<Value, ParseError> {
+    evaluate_in(expr, &Scope::new(env))
+}
+
+// Call a user defined function. Its body sees its own parameters and the session's variables,
+// but not the parameters of its caller.
+fn call<'a>(name: &str, function: &'a UserFunction, args: &[Expr], expr: &Expr, scope: &Scope<'a>) -> Result<Value, ParseError> {
+    if args.len() != function.params.len() {
+        let kind = ErrorKind::WrongArgumentCount(name.to_owned(), function.params.len(), args.len());
+        return Err(ParseError::new(kind, expr.span));
+    }
+    if scope.depth >= MAX_CALL_DEPTH {
+        return Err(ParseError::new(ErrorKind::RecursionLimit(name.to_owned()), expr.span));
+    }
+
+    let mut params = Vec::with_capacity(args.len());
+    for (param, arg) in function.params.iter().zip(args) {
+        params.push((param.as_str(), try!(evaluate_in(arg, scope))));
+    }
+    let inner = Scope {
+        env: scope.env,
+        params: params,
+        depth: scope.depth + 1,
+    };
+    evaluate_in(&function.body, &inner)
+}
+
+fn apply_binary(operator: BinaryOperator, a: &Value, b: &Value) -> Result<Value, ErrorKind> {
+    let ordering = |test: fn(Ordering) -> bool| a.compare(b).map(|o| Value::Bool(o.map_or(false, test)));
+    match operator {
+        BinaryOperator::Add          => a.add(b),
+        BinaryOperator::Subtract     => a.sub(b),
+        BinaryOperator::Multiply     => a.mul(b),
+        BinaryOperator::Divide       => a.div(b),
+        BinaryOperator::IntDivide    => a.int_div(b),
+        BinaryOperator::Modulo       => a.modulo(b),
+        BinaryOperator::Exponent     => a.pow(b),
+        BinaryOperator::BitAnd       => a.bit_and(b),
+        BinaryOperator::BitOr        => a.bit_or(b),
+        BinaryOperator::BitXor       => a.bit_xor(b),
+        BinaryOperator::ShiftLeft    => a.shl(b),
+        BinaryOperator::ShiftRight   => a.shr(b),
+        BinaryOperator::Equal        => a.equals(b).map(Value::Bool),
+        BinaryOperator::NotEqual     => a.equals(b).map(|equal| Value::Bool(!equal)),
+        BinaryOperator::Less         => ordering(|o| o == Ordering::Less),
+        BinaryOperator::LessEqual    => ordering(|o| o != Ordering::Greater),
+        BinaryOperator::Greater      => ordering(|o| o == Ordering::Greater),
+        BinaryOperator::GreaterEqual => ordering(|o| o != Ordering::Less),
+        BinaryOperator::And | BinaryOperator::Or => a.truth().and_then(|a| b.truth().map(|b| {
+            Value::Bool(if operator == BinaryOperator::And { a && b } else { a || b })
+        })),
+    }
+}
+
+// Each kind of expression is evaluated in its own function, to keep the stack frames of deeply
+// recursive user defined functions small
+fn evaluate_in<'a>(expr: &Expr, scope: &Scope<'a>) -> Result<Value, ParseError> {
+    match expr.kind {
+        ExprKind::Number(ref n) => at(scope.env.number(n), expr.span),
+        ExprKind::Variable(ref name) => at(scope.get(name), expr.span),
+        ExprKind::Call(ref name, ref args) => evaluate_call(name, args, expr, scope),
+        ExprKind::Unary(operator, ref operand) => evaluate_unary(operator, operand, expr, scope),
+        ExprKind::Binary(operator, ref lhs, ref rhs) => evaluate_binary(operator, lhs, rhs, expr, scope),
+        ExprKind::Conditional(ref condition, ref then, ref otherwise) => {
+            if try!(evaluate_condition(condition, scope)) {
+                evaluate_in(then, scope)
+            } else {
+                evaluate_in(otherwise, scope)
+            }
+        }
+        ExprKind::Quantity(ref number, ref name) => evaluate_unit(number, name, false, expr, scope),
+        ExprKind::Convert(ref operand, ref name) => evaluate_unit(operand, name, true, expr, scope),
+        ExprKind::Percent(ref operand) => evaluate_percent(operand, expr, scope),
+    }
+}
+
+fn evaluate_call<'a>(name: &str, args: &[Expr], expr: &Expr, scope: &Scope<'a>) -> Result<Value, ParseError> {
+    if let Some(function) = scope.env.function(name) {
+        return call(name, function, args, expr, scope);
+    }
+    let function = try!(at(find_function(name).ok_or_else(|| ErrorKind::UnknownFunction(name.to_owned())),
+                           expr.span));
+    if args.len() < function.min_args || args.len() > function.max_args {
+        let expected = if args.len() < function.min_args { function.min_args } else { function.max_args };
+        let kind = ErrorKind::WrongArgumentCount(name.to_owned(), expected, args.len());
+        return Err(ParseError::new(kind, expr.span));
+    }
+
+    let mut values = Vec::with_capacity(args.len());
+    let mut exact = Vec::new();
+    for arg in args {
+        let value = try!(evaluate_in(arg, scope));
+        try!(at(value.number(), arg.span));
+        match (try!(at(value.unitless(), arg.span)), function.complex) {
+            (&Value::Complex(z), Some(complex)) if args.len() == 1 => return Ok(Value::from_complex(complex(z))),
+            (&Value::Complex(_), _) => {
+                return Err(ParseError::new(ErrorKind::TypeMismatch("real number", "complex number"), arg.span));
+            }
+            (&Value::Exact(ref value), _) => {
+                values.push(value.to_f64());
+                exact.push(value.clone());
+            }
+            (value, _) => values.push(value.to_f64()),
+        }
+    }
+
+    // Keep the result exact when every argument is
+    match function.exact {
+        Some(apply) if exact.len() == values.len() => return Ok(Value::Exact(apply(&exact))),
+        _ => (),
+    }
+    let result = (function.apply)(&values);
+    match function.complex {
+        Some(complex) if result.is_nan() && !values[0].is_nan() => {
+            Ok(Value::from_complex(complex(Complex::new(values[0], 0.0))))
+        }
+        _ => Ok(Value::Float(result)),
+    }
+}
+
+fn evaluate_unary<'a>(operator: UnaryOperator, operand: &Expr, expr: &Expr, scope: &Scope<'a>) -> Result<Value, ParseError> {
+    if operator == UnaryOperator::Not {
+        return evaluate_condition(operand, scope).map(|b| Value::Bool(!b));
+    }
+    let value = try!(evaluate_in(operand, scope));
+    try!(at(value.number(), operand.span));
+    match operator {
+        UnaryOperator::BitNot => at(value.bit_not(), expr.span),
+        _ => Ok(value.neg()),
+    }
+}
+
+fn evaluate_binary<'a>(operator: BinaryOperator, lhs: &Expr, rhs: &Expr, expr: &Expr, scope: &Scope<'a>)
+    -> Result<Value, ParseError>
+{
+    // The right hand side of a logical operator is only evaluated when it decides the result
+    if operator == BinaryOperator::And || operator == BinaryOperator::Or {
+        let a = try!(evaluate_condition(lhs, scope));
+        if a == (operator == BinaryOperator::Or) {
+            return Ok(Value::Bool(a));
+        }
+        return evaluate_condition(rhs, scope).map(Value::Bool);
+    }
+
+    let a = try!(evaluate_in(lhs, scope));
+    let b = try!(evaluate_rhs(operator, &a, rhs, scope));
+    match operator {
+        BinaryOperator::Equal | BinaryOperator::NotEqual | BinaryOperator::Less |
+        BinaryOperator::LessEqual | BinaryOperator::Greater | BinaryOperator::GreaterEqual => (),
+        // Durations can be added to and taken from dates
+        BinaryOperator::Add | BinaryOperator::Subtract if a.is_date() || b.is_date() => (),
+        _ => {
+            try!(at(a.number(), lhs.span));
+            try!(at(b.number(), rhs.span));
+        }
+    }
+    at(apply_binary(operator, &a, &b), expr.span)
+}
+
+// The right hand side of a binary operator. `x + y%` and `x - y%` add or take away y percent of x,
+// as do scaled percentages such as `x + y% * 2`.
+fn evaluate_rhs<'a>(operator: BinaryOperator, a: &Value, rhs: &Expr, scope: &Scope<'a>) -> Result<Value, ParseError> {
+    match operator {
+        BinaryOperator::Add | BinaryOperator::Subtract if is_percentage(rhs) => {
+            let fraction = try!(evaluate_in(rhs, scope));
+            at(a.mul(&fraction), rhs.span)
+        }
+        _ => evaluate_in(rhs, scope),
+    }
+}
+
+// Whether an expression is a percentage, negated or multiplied or divided by a number
+fn is_percentage(expr: &Expr) -> bool {
+    match expr.kind {
+        ExprKind::Percent(_) => true,
+        ExprKind::Unary(UnaryOperator::Negate, ref operand) => is_percentage(operand),
+        ExprKind::Binary(BinaryOperator::Multiply, ref lhs, ref rhs) => is_percentage(lhs) || is_percentage(rhs),
+        ExprKind::Binary(BinaryOperator::Divide, ref lhs, _) => is_percentage(lhs),
+        _ => false,
+    }
+}
+
+// Evaluate an expression which must produce a boolean
+fn evaluate_condition<'a>(expr: &Expr, scope: &Scope<'a>) -> Result<bool, ParseError> {
+    let value = try!(evaluate_in(expr, scope));
+    at(value.truth(), expr.span)
+}
+
+// A percentage, as a fraction of one
+fn evaluate_percent<'a>(operand: &Expr, expr: &Expr, scope: &Scope<'a>) -> Result<Value, ParseError> {
+    let value = try!(evaluate_in(operand, scope));
+    try!(at(value.number(), operand.span));
+    at(value.div(&Value::Exact(Rational::from_integer(BigInt::from_u64(100)))), expr.span)
+}
+
+// A number with a unit suffix, or a quantity converted to another unit with `in`. A variable
+// named like the unit takes precedence over it, so that `m = 3; 2 m` is 6.
+fn evaluate_unit<'a>(operand: &Expr, name: &str, convert: bool, expr: &Expr, scope: &Scope<'a>) -> Result<Value, ParseError> {
+    if !convert && scope.defines(name) {
+        let value = try!(evaluate_in(operand, scope));
+        return at(scope.get(name).and_then(|variable| value.mul(&variable)), expr.span);
+    }
+    let unit = try!(at(find_unit(name).ok_or_else(|| ErrorKind::UnknownUnit(name.to_owned())), expr.span));
+    let value = try!(evaluate_in(operand, scope));
+    try!(at(value.number(), operand.span));
+    if convert {
+        at(value.convert(&unit), expr.span)
+    } else {
+        at(value.mul(&Value::Exact(unit.factor.clone())), expr.span)
+            .map(|magnitude| Value::with_unit(magnitude, Some(unit)))
+    }
+}
+
+pub fn parse(tokens: Vec<SpannedToken>) -> Result<String, ParseError> {
+    parse_with_env(tokens, &mut Environment::new())
+}
+
+/// Run an expression or assignment, storing the result in `ans`, and in the variable being
+/// assigned if any. Function definitions have no value and are rejected with an error at `span`.
+pub fn execute(statement: Statement, span: Span, env: &mut Environment) -> Result<Value, ParseError> {
+    let (target, expr) = match statement {
+        Statement::Expression(expr) => (None, expr),
+        Statement::Assignment(name, expr) => (Some(name), expr),
+        Statement::Definition(..) => {
+            let kind = ErrorKind::OtherError("Function definitions have no value".to_owned());
+            return Err(ParseError::new(kind, span));
+        }
+    };
+
+    let value = try!(evaluate(&expr, env));
+    if let Some(name) = target {
+        env.set(&name, value.clone());
+    }
+    env.set("ans", value.clone());
+    Ok(value)
+}
+
+/// Evaluate a line, storing the result in `ans` so that the next line can refer to it. Function
+/// definitions are stored in the environment and echoed back.
+pub fn parse_with_env(tokens: Vec<SpannedToken>, env: &mut Environment) -> Result<String, ParseError> {
+    match try!(Parser::new(&tokens).parse_statement()) {
+        Statement::Definition(name, params, body) => {
+            let definition = format!("{}({}) = {}", name, params.join(", "), body);
+            let function = UserFunction {
+                params: params,
+                body: body,
+            };
+            try!(at(env.define(&name, function), tokens[0].span));
+            Ok(definition)
+        }
+        statement => {
+            let span = Span::new(0, tokens.last().map_or(0, |token| token.span.end));
+            let value = try!(execute(statement, span, env));
+            Ok(env.format.value(&value))
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Writing values out in a radix, precision and notation.
+
+use super::date::{format_date, format_duration};
+use super::error::ErrorKind;
+use super::number::BigInt;
+use super::unit::duration_unit;
+use super::value::Value;
+
+/// How real numbers are written: plain decimals, scientific notation such as `1.5e3`, or
+/// engineering notation, which keeps exponents to multiples of three.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Notation {
+    Plain,
+    Scientific,
+    Engineering,
+}
+
+/// Output settings, applied to values after they have been evaluated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Format {
+    /// The radix integers are written in; one of 2, 8, 10 or 16.
+    pub radix: u32,
+    /// The most digits written after the decimal point, or `None` for as many as a float needs.
+    /// In scientific and engineering notation this applies to the mantissa.
+    pub precision: Option<usize>,
+    pub notation: Notation,
+    /// Separate digit groups, with `,` every three decimal digits and `_` every four digits in
+    /// other radixes.
+    pub group: bool,
+    /// The time zone dates are read and written in, as a number of seconds east of UTC.
+    pub offset: i64,
+}
+
+impl Format {
+    pub fn new() -> Self {
+        Format {
+            radix: 10,
+            precision: None,
+            notation: Notation::Plain,
+            group: false,
+            offset: 0,
+        }
+    }
+
+    pub fn set_radix(&mut self, radix: u32) -> Result<(), ErrorKind> {
+        match radix {
+            2 | 8 | 10 | 16 => {
+                self.radix = radix;
+                Ok(())
+            }
+            _ => Err(ErrorKind::OtherError(format!("Unsupported base: {}", radix))),
+        }
+    }
+
+    /// Write a value. Integers are written in the output radix with a `0x`, `0o` or `0b` prefix,
+    /// and exact values are written exactly unless a precision or notation asks otherwise.
+    pub fn value(&self, value: &Value) -> String {
+        match *value {
+            Value::Float(x) => match BigInt::from_f64(x) {
+                Some(ref n) if self.radix != 10 => self.integer(n),
+                _ => self.real(x),
+            },
+            Value::Exact(ref r) if r.is_integer() && (self.radix != 10 || self.notation == Notation::Plain) => {
+                self.integer(r.numerator())
+            }
+            Value::Exact(ref r) if self.notation == Notation::Plain && self.precision.is_none() => {
+                let parts: Vec<String> = r.to_string().split('/').map(|part| self.grouped(part)).collect();
+                parts.join("/")
+            }
+            Value::Exact(ref r) => self.real(r.to_f64()),
+            Value::Complex(c) => c.format(|x| self.real(x)),
+            Value::Quantity(ref magnitude, ref unit) if *unit == duration_unit() => format_duration(magnitude.to_f64()),
+            Value::Quantity(ref magnitude, ref unit) => match magnitude.div(&Value::Exact(unit.factor.clone())) {
+                Ok(n) => [self.value(&n), " ".to_owned(), unit.name.clone()].concat(),
+                Err(_) => value.to_string(),
+            },
+            Value::Bool(b) => b.to_string(),
+            Value::Date(ref seconds) => format_date(seconds.to_f64(), self.offset),
+        }
+    }
+
+    fn integer(&self, n: &BigInt) -> String {
+        let sign = if n.is_negative() { "-" } else { "" };
+        let digits = n.abs().to_string_radix(self.radix);
+        let (prefix, size, separator) = match self.radix {
+            16 => ("0x", 4, '_'),
+            8 => ("0o", 4, '_'),
+            2 => ("0b", 4, '_'),
+            _ => ("", 3, ','),
+        };
+        if self.group {
+            [sign, prefix, &group_digits(&digits, size, separator)].concat()
+        } else {
+            [sign, prefix, &digits].concat()
+        }
+    }
+
+    /// Write a real number in decimal, in the current notation and precision.
+    pub fn real(&self, x: f64) -> String {
+        if !x.is_finite() {
+            return x.to_string();
+        }
+        let s = match (self.notation, self.precision) {
+            (Notation::Plain, Some(precision)) => trim_zeros(&format!("{:.*}", precision, x)),
+            (Notation::Plain, None) => x.to_string(),
+            (Notation::Scientific, precision) => scientific(x, precision, 1),
+            (Notation::Engineering, precision) => scientific(x, precision, 3),
+        };
+        // Rounding a small negative number leaves a negative zero
+        let s = if s == "-0" { "0".to_owned() } else { s };
+        self.grouped(&s)
+    }
+
+    // Group the integer part of a plain decimal number
+    fn grouped(&self, s: &str) -> String {
+        if !self.group || self.notation != Notation::Plain {
+            return s.to_owned();
+        }
+        let start = if s.starts_with('-') { 1 } else { 0 };
+        let end = s[start..].find(|c: char| !c.is_digit(10)).map_or(s.len(), |i| start + i);
+        [&s[..start], &group_digits(&s[start..end], 3, ','), &s[end..]].concat()
+    }
+}
+
+// Separate a run of digits into groups of `size`, counting from the right
+fn group_digits(digits: &str, size: usize, separator: char) -> String {
+    let mut result = String::with_capacity(digits.len() * 2);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % size == 0 {
+            result.push(separator);
+        }
+        result.push(c);
+    }
+    result
+}
+
+// Remove trailing zeros after a decimal point, and the point itself if nothing follows it
+fn trim_zeros(s: &str) -> String {
+    if s.contains('.') {
+        s.trim_right_matches('0').trim_right_matches('.').to_owned()
+    } else {
+        s.to_owned()
+    }
+}
+
+// Write `x` as a mantissa and a power of ten which is a multiple of `step`
+fn scientific(x: f64, precision: Option<usize>, step: i32) -> String {
+    let s = match precision {
+        Some(precision) => format!("{:.*e}", precision, x),
+        None => format!("{:e}", x),
+    };
+    let (mantissa, exponent) = s.split_at(s.find('e').unwrap());
+    let exponent: i32 = exponent[1..].parse().unwrap();
+    let shift = ((exponent % step) + step) % step;
+    if shift == 0 {
+        return format!("{}e{}", trim_zeros(mantissa), exponent);
+    }
+
+    // Move the decimal point right, padding with zeros if the mantissa runs out of digits
+    let sign = if mantissa.starts_with('-') { "-" } else { "" };
+    let mut digits: String = mantissa.chars().filter(|c| c.is_digit(10)).collect();
+    while digits.len() < shift as usize + 1 {
+        digits.push('0');
+    }
+    let (integer, fraction) = digits.split_at(shift as usize + 1);
+    let mantissa = if fraction.is_empty() {
+        integer.to_owned()
+    } else {
+        trim_zeros(&[integer, ".", fraction].concat())
+    };
+    format!("{}{}e{}", sign, mantissa, exponent - shift)
+}
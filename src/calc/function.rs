@@ -0,0 +1,146 @@
+//! Built in functions and constants.
+
+use std::cmp::Ordering;
+
+use super::number::{BigInt, Complex, Rational};
+
+/// A built-in function taking between `min_args` and `max_args` arguments. Functions which extend
+/// to the complex plane have a `complex` version, which is also used when the real version of the
+/// function is undefined, as for `sqrt(-1)`. Functions with an `exact` version use it when every
+/// argument is exact, so that their result stays exact.
+pub struct Function {
+    pub name: &'static str,
+    pub min_args: usize,
+    pub max_args: usize,
+    pub apply: fn(&[f64]) -> f64,
+    pub complex: Option<fn(Complex) -> Complex>,
+    pub exact: Option<fn(&[Rational]) -> Rational>,
+}
+
+/// The `max_args` of functions which take any number of arguments.
+pub const VARIADIC: usize = std::usize::MAX;
+
+pub static FUNCTIONS: &'static [Function] = &[
+    Function { name: "sin",   min_args: 1, max_args: 1, apply: builtin_sin,   complex: None, exact: None },
+    Function { name: "cos",   min_args: 1, max_args: 1, apply: builtin_cos,   complex: None, exact: None },
+    Function { name: "tan",   min_args: 1, max_args: 1, apply: builtin_tan,   complex: None, exact: None },
+    Function { name: "asin",  min_args: 1, max_args: 1, apply: builtin_asin,  complex: None, exact: None },
+    Function { name: "acos",  min_args: 1, max_args: 1, apply: builtin_acos,  complex: None, exact: None },
+    Function { name: "atan",  min_args: 1, max_args: 1, apply: builtin_atan,  complex: None, exact: None },
+    Function { name: "sqrt",  min_args: 1, max_args: 1, apply: builtin_sqrt,  complex: Some(Complex::sqrt), exact: None },
+    Function { name: "exp",   min_args: 1, max_args: 1, apply: builtin_exp,   complex: Some(Complex::exp), exact: None },
+    Function { name: "ln",    min_args: 1, max_args: 1, apply: builtin_ln,    complex: Some(Complex::ln), exact: None },
+    Function { name: "log",   min_args: 1, max_args: 2, apply: builtin_log,   complex: None, exact: None },
+    Function { name: "abs",   min_args: 1, max_args: 1, apply: builtin_abs,   complex: Some(complex_abs), exact: None },
+    Function { name: "floor", min_args: 1, max_args: 1, apply: builtin_floor, complex: None, exact: None },
+    Function { name: "ceil",  min_args: 1, max_args: 1, apply: builtin_ceil,  complex: None, exact: None },
+    Function { name: "round", min_args: 1, max_args: 1, apply: builtin_round, complex: None, exact: None },
+    Function { name: "re",    min_args: 1, max_args: 1, apply: builtin_re,    complex: Some(complex_re), exact: None },
+    Function { name: "im",    min_args: 1, max_args: 1, apply: builtin_im,    complex: Some(complex_im), exact: None },
+    Function { name: "arg",   min_args: 1, max_args: 1, apply: builtin_arg,   complex: Some(complex_arg), exact: None },
+    Function { name: "conj",  min_args: 1, max_args: 1, apply: builtin_re,    complex: Some(Complex::conj), exact: None },
+    Function { name: "sum",    min_args: 1, max_args: VARIADIC, apply: builtin_sum,    complex: None, exact: Some(exact_sum) },
+    Function { name: "mean",   min_args: 1, max_args: VARIADIC, apply: builtin_mean,   complex: None, exact: Some(exact_mean) },
+    Function { name: "median", min_args: 1, max_args: VARIADIC, apply: builtin_median, complex: None, exact: Some(exact_median) },
+    Function { name: "min",    min_args: 1, max_args: VARIADIC, apply: builtin_min,    complex: None, exact: Some(exact_min) },
+    Function { name: "max",    min_args: 1, max_args: VARIADIC, apply: builtin_max,    complex: None, exact: Some(exact_max) },
+    Function { name: "stddev", min_args: 2, max_args: VARIADIC, apply: builtin_stddev, complex: None, exact: None },
+    Function { name: "var",    min_args: 2, max_args: VARIADIC, apply: builtin_var,    complex: None, exact: Some(exact_var) },
+];
+
+pub static CONSTANTS: &'static [(&'static str, f64)] = &[
+    ("pi", std::f64::consts::PI),
+    ("e", std::f64::consts::E),
+];
+
+fn builtin_sin(args: &[f64]) -> f64 { args[0].sin() }
+fn builtin_cos(args: &[f64]) -> f64 { args[0].cos() }
+fn builtin_tan(args: &[f64]) -> f64 { args[0].tan() }
+fn builtin_asin(args: &[f64]) -> f64 { args[0].asin() }
+fn builtin_acos(args: &[f64]) -> f64 { args[0].acos() }
+fn builtin_atan(args: &[f64]) -> f64 { args[0].atan() }
+fn builtin_sqrt(args: &[f64]) -> f64 { args[0].sqrt() }
+fn builtin_exp(args: &[f64]) -> f64 { args[0].exp() }
+fn builtin_ln(args: &[f64]) -> f64 { args[0].ln() }
+fn builtin_abs(args: &[f64]) -> f64 { args[0].abs() }
+fn builtin_floor(args: &[f64]) -> f64 { args[0].floor() }
+fn builtin_ceil(args: &[f64]) -> f64 { args[0].ceil() }
+fn builtin_round(args: &[f64]) -> f64 { args[0].round() }
+fn builtin_re(args: &[f64]) -> f64 { args[0] }
+fn builtin_im(_: &[f64]) -> f64 { 0.0 }
+fn builtin_arg(args: &[f64]) -> f64 { 0f64.atan2(args[0]) }
+
+fn builtin_sum(args: &[f64]) -> f64 { args.iter().sum() }
+fn builtin_mean(args: &[f64]) -> f64 { builtin_sum(args) / args.len() as f64 }
+fn builtin_min(args: &[f64]) -> f64 { args.iter().fold(std::f64::INFINITY, |a, &b| a.min(b)) }
+fn builtin_max(args: &[f64]) -> f64 { args.iter().fold(std::f64::NEG_INFINITY, |a, &b| a.max(b)) }
+fn builtin_stddev(args: &[f64]) -> f64 { builtin_var(args).sqrt() }
+
+fn builtin_median(args: &[f64]) -> f64 {
+    let mut sorted = args.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let middle = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+// The sample variance, which needs at least two values
+fn builtin_var(args: &[f64]) -> f64 {
+    let mean = builtin_mean(args);
+    let squares: f64 = args.iter().map(|x| (x - mean) * (x - mean)).sum();
+    squares / (args.len() as f64 - 1.0)
+}
+
+fn integer(n: usize) -> Rational { Rational::from_integer(BigInt::from_u64(n as u64)) }
+
+fn exact_sum(args: &[Rational]) -> Rational {
+    args.iter().fold(integer(0), |a, b| a.add(b))
+}
+
+fn exact_mean(args: &[Rational]) -> Rational { exact_sum(args).div(&integer(args.len())).unwrap() }
+
+fn exact_min(args: &[Rational]) -> Rational {
+    args.iter().min_by(|a, b| a.compare(b)).unwrap().clone()
+}
+
+fn exact_max(args: &[Rational]) -> Rational {
+    args.iter().max_by(|a, b| a.compare(b)).unwrap().clone()
+}
+
+fn exact_median(args: &[Rational]) -> Rational {
+    let mut sorted = args.to_vec();
+    sorted.sort_by(|a, b| a.compare(b));
+    let middle = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        sorted[middle - 1].add(&sorted[middle]).div(&integer(2)).unwrap()
+    } else {
+        sorted[middle].clone()
+    }
+}
+
+fn exact_var(args: &[Rational]) -> Rational {
+    let mean = exact_mean(args);
+    let squares = args.iter().fold(integer(0), |a, x| a.add(&x.sub(&mean).mul(&x.sub(&mean))));
+    squares.div(&integer(args.len() - 1)).unwrap()
+}
+
+fn complex_abs(z: Complex) -> Complex { Complex::new(z.abs(), 0.0) }
+fn complex_re(z: Complex) -> Complex { Complex::new(z.re, 0.0) }
+fn complex_im(z: Complex) -> Complex { Complex::new(z.im, 0.0) }
+fn complex_arg(z: Complex) -> Complex { Complex::new(z.arg(), 0.0) }
+
+// `log(x)` is the common logarithm, `log(x, b)` the logarithm to base `b`.
+fn builtin_log(args: &[f64]) -> f64 {
+    if args.len() == 2 {
+        args[0].log(args[1])
+    } else {
+        args[0].log10()
+    }
+}
+
+pub fn find_function(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|f| f.name == name)
+}
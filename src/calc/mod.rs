@@ -0,0 +1,89 @@
+//! The expression engine behind `calc`: arbitrary precision, exact and complex arithmetic with
+//! units, user defined functions and configurable output.
+//!
+//! Other utilities can evaluate arithmetic with an `Evaluator`:
+//!
+//! ```
+//! use extrautils::calc::{Evaluator, Value};
+//!
+//! let mut evaluator = Evaluator::new();
+//! evaluator.evaluate("minutes = 90").unwrap();
+//! assert_eq!(evaluator.evaluate("minutes * 60").unwrap().to_f64(), 5400.0);
+//! ```
+
+mod date;
+mod error;
+mod eval;
+mod format;
+mod function;
+mod number;
+mod parser;
+mod token;
+mod unit;
+mod value;
+
+#[cfg(test)]
+mod property;
+#[cfg(test)]
+mod test;
+
+pub use self::date::{format_offset, parse_offset};
+pub use self::error::{ErrorKind, ParseError};
+pub use self::eval::{evaluate, execute, parse, parse_with_env, Environment, UserFunction};
+pub use self::format::{Format, Notation};
+pub use self::function::{find_function, Function};
+pub use self::number::{BigInt, Complex, Rational};
+pub use self::parser::{BinaryOperator, Expr, ExprKind, Parser, Statement, UnaryOperator};
+pub use self::token::{tokenize, Span, SpannedToken, Token};
+pub use self::unit::{find_unit, Dimension, Unit};
+pub use self::value::Value;
+
+/// A calculator session: the variables and functions defined so far, and the settings results are
+/// formatted with.
+#[derive(Clone, Debug)]
+pub struct Evaluator {
+    env: Environment,
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
+        Evaluator {
+            env: Environment::new(),
+        }
+    }
+
+    /// A session which keeps integers and fractions exact rather than using floating point.
+    pub fn exact() -> Self {
+        Evaluator {
+            env: Environment::exact(),
+        }
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.env
+    }
+
+    pub fn environment_mut(&mut self) -> &mut Environment {
+        &mut self.env
+    }
+
+    /// Evaluate an expression, or an assignment of the form `name = expr`, and return its value.
+    /// The result is also stored in `ans`.
+    pub fn evaluate(&mut self, input: &str) -> Result<Value, ParseError> {
+        let tokens = try!(tokenize(input));
+        let statement = try!(Parser::new(&tokens).parse_statement());
+        execute(statement, Span::new(0, input.len()), &mut self.env)
+    }
+
+    /// Run a line as `calc` does, accepting function definitions as well, and return the result
+    /// formatted with the session's settings.
+    pub fn run(&mut self, input: &str) -> Result<String, ParseError> {
+        let tokens = try!(tokenize(input));
+        parse_with_env(tokens, &mut self.env)
+    }
+
+    /// Write a value with the session's radix, precision and notation.
+    pub fn format(&self, value: &Value) -> String {
+        self.env.format.value(value)
+    }
+}
@@ -0,0 +1,643 @@
+//! Arbitrary precision integers and fractions for exact arithmetic, and complex numbers.
+
+use std::cmp::{self, Ordering};
+use std::fmt;
+
+/// An arbitrary-precision integer, stored as a sign and little-endian base 2^32 digits.
+/// The digits never have trailing zeros, so zero is the empty vector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>,
+}
+
+fn trim(digits: &mut Vec<u32>) {
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+}
+
+fn cmp_digits(a: &[u32], b: &[u32]) -> Ordering {
+    if a.len() != b.len() {
+        return a.len().cmp(&b.len());
+    }
+    for i in (0..a.len()).rev() {
+        if a[i] != b[i] {
+            return a[i].cmp(&b[i]);
+        }
+    }
+    Ordering::Equal
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for i in 0..long.len() {
+        let sum = long[i] as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+// Requires a >= b
+fn sub_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for i in 0..a.len() {
+        let mut diff = a[i] as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        } else {
+            borrow = 0;
+        }
+        result.push(diff as u32);
+    }
+    trim(&mut result);
+    result
+}
+
+fn mul_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut result = vec![0u32; a.len() + b.len()];
+    for i in 0..a.len() {
+        let mut carry = 0u64;
+        for j in 0..b.len() {
+            let cur = result[i + j] as u64 + a[i] as u64 * b[j] as u64 + carry;
+            result[i + j] = cur as u32;
+            carry = cur >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    trim(&mut result);
+    result
+}
+
+// Two's complement negation in place, ignoring overflow
+fn negate_digits(digits: &mut Vec<u32>) {
+    let mut carry = 1u64;
+    for d in digits.iter_mut() {
+        let cur = (!*d) as u64 + carry;
+        *d = cur as u32;
+        carry = cur >> 32;
+    }
+}
+
+// Divide in place by a single digit, returning the remainder
+fn div_small(digits: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut rem = 0u64;
+    for i in (0..digits.len()).rev() {
+        let cur = (rem << 32) | digits[i] as u64;
+        digits[i] = (cur / divisor as u64) as u32;
+        rem = cur % divisor as u64;
+    }
+    trim(digits);
+    rem as u32
+}
+
+fn shl1_digits(digits: &mut Vec<u32>, bit: u32) {
+    let mut carry = bit;
+    for d in digits.iter_mut() {
+        let next = *d >> 31;
+        *d = (*d << 1) | carry;
+        carry = next;
+    }
+    if carry > 0 {
+        digits.push(carry);
+    }
+}
+
+// Schoolbook binary long division; requires a non-zero divisor
+fn divrem_digits(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_digits(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if b.len() == 1 {
+        let mut quotient = a.to_vec();
+        let rem = div_small(&mut quotient, b[0]);
+        let mut rem = vec![rem];
+        trim(&mut rem);
+        return (quotient, rem);
+    }
+    let mut quotient = vec![0u32; a.len()];
+    let mut rem = Vec::new();
+    for i in (0..a.len() * 32).rev() {
+        shl1_digits(&mut rem, (a[i / 32] >> (i % 32)) & 1);
+        if cmp_digits(&rem, b) != Ordering::Less {
+            rem = sub_digits(&rem, b);
+            quotient[i / 32] |= 1 << (i % 32);
+        }
+    }
+    trim(&mut quotient);
+    (quotient, rem)
+}
+
+impl BigInt {
+    pub fn zero() -> Self {
+        BigInt { negative: false, digits: Vec::new() }
+    }
+
+    pub fn from_u64(n: u64) -> Self {
+        let mut digits = vec![n as u32, (n >> 32) as u32];
+        trim(&mut digits);
+        BigInt { negative: false, digits: digits }
+    }
+
+    fn from_digits(negative: bool, mut digits: Vec<u32>) -> Self {
+        trim(&mut digits);
+        let negative = negative && !digits.is_empty();
+        BigInt { negative: negative, digits: digits }
+    }
+
+    /// Parse a string of digits in the given radix.
+    pub fn parse_radix(s: &str, radix: u32) -> Option<Self> {
+        if s.is_empty() {
+            return None;
+        }
+        let mut digits = Vec::new();
+        for c in s.chars() {
+            let d = match c.to_digit(radix) {
+                Some(d) => d,
+                None => return None,
+            };
+            let mut carry = d as u64;
+            for digit in digits.iter_mut() {
+                let cur = *digit as u64 * radix as u64 + carry;
+                *digit = cur as u32;
+                carry = cur >> 32;
+            }
+            if carry > 0 {
+                digits.push(carry as u32);
+            }
+        }
+        Some(BigInt::from_digits(false, digits))
+    }
+
+    /// Convert an integral float, or `None` if it has a fractional part or is not finite.
+    pub fn from_f64(f: f64) -> Option<Self> {
+        if !f.is_finite() || f.fract() != 0.0 {
+            return None;
+        }
+        let mut magnitude = f.abs();
+        let mut digits = Vec::new();
+        while magnitude > 0.0 {
+            digits.push((magnitude % 4294967296.0) as u32);
+            magnitude = (magnitude / 4294967296.0).floor();
+        }
+        Some(BigInt::from_digits(f < 0.0, digits))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn is_one(&self) -> bool {
+        !self.negative && self.digits == [1]
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_even(&self) -> bool {
+        self.digits.first().map_or(true, |d| d & 1 == 0)
+    }
+
+    pub fn neg(&self) -> Self {
+        BigInt::from_digits(!self.negative, self.digits.clone())
+    }
+
+    pub fn abs(&self) -> Self {
+        BigInt::from_digits(false, self.digits.clone())
+    }
+
+    pub fn add(&self, other: &BigInt) -> Self {
+        if self.negative == other.negative {
+            BigInt::from_digits(self.negative, add_digits(&self.digits, &other.digits))
+        } else if cmp_digits(&self.digits, &other.digits) != Ordering::Less {
+            BigInt::from_digits(self.negative, sub_digits(&self.digits, &other.digits))
+        } else {
+            BigInt::from_digits(other.negative, sub_digits(&other.digits, &self.digits))
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> Self {
+        BigInt::from_digits(self.negative != other.negative, mul_digits(&self.digits, &other.digits))
+    }
+
+    /// Truncating division, returning the quotient and remainder. The divisor must not be zero.
+    pub fn div_rem(&self, other: &BigInt) -> (BigInt, BigInt) {
+        let (q, r) = divrem_digits(&self.digits, &other.digits);
+        (BigInt::from_digits(self.negative != other.negative, q), BigInt::from_digits(self.negative, r))
+    }
+
+    /// Floored division, so that the remainder takes the sign of the divisor. The divisor must not
+    /// be zero.
+    pub fn div_floor(&self, other: &BigInt) -> (BigInt, BigInt) {
+        let (q, r) = self.div_rem(other);
+        if !r.is_zero() && r.negative != other.negative {
+            (q.sub(&BigInt::from_u64(1)), r.add(other))
+        } else {
+            (q, r)
+        }
+    }
+
+    // Two's complement digits, sign extended to `len` digits
+    fn to_twos_complement(&self, len: usize) -> Vec<u32> {
+        let mut digits = self.digits.clone();
+        digits.resize(len, 0);
+        if self.negative {
+            negate_digits(&mut digits);
+        }
+        digits
+    }
+
+    fn from_twos_complement(mut digits: Vec<u32>) -> Self {
+        let negative = digits.last().map_or(false, |d| d >> 31 == 1);
+        if negative {
+            negate_digits(&mut digits);
+        }
+        BigInt::from_digits(negative, digits)
+    }
+
+    fn bitwise<F: Fn(u32, u32) -> u32>(&self, other: &BigInt, op: F) -> Self {
+        let len = cmp::max(self.digits.len(), other.digits.len()) + 1;
+        let a = self.to_twos_complement(len);
+        let b = other.to_twos_complement(len);
+        BigInt::from_twos_complement(a.iter().zip(b.iter()).map(|(&x, &y)| op(x, y)).collect())
+    }
+
+    pub fn bit_and(&self, other: &BigInt) -> Self {
+        self.bitwise(other, |x, y| x & y)
+    }
+
+    pub fn bit_or(&self, other: &BigInt) -> Self {
+        self.bitwise(other, |x, y| x | y)
+    }
+
+    pub fn bit_xor(&self, other: &BigInt) -> Self {
+        self.bitwise(other, |x, y| x ^ y)
+    }
+
+    pub fn bit_not(&self) -> Self {
+        self.neg().sub(&BigInt::from_u64(1))
+    }
+
+    pub fn shl(&self, bits: u64) -> Self {
+        self.mul(&BigInt::from_u64(2).pow(bits))
+    }
+
+    /// Arithmetic shift right, rounding towards negative infinity.
+    pub fn shr(&self, bits: u64) -> Self {
+        self.div_floor(&BigInt::from_u64(2).pow(bits)).0
+    }
+
+    pub fn pow(&self, mut exponent: u64) -> Self {
+        let mut base = self.clone();
+        let mut result = BigInt::from_u64(1);
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.mul(&base);
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.mul(&base);
+            }
+        }
+        result
+    }
+
+    /// The greatest common divisor of the absolute values.
+    pub fn gcd(&self, other: &BigInt) -> Self {
+        let mut a = self.abs();
+        let mut b = other.abs();
+        while !b.is_zero() {
+            let r = a.div_rem(&b).1;
+            a = b;
+            b = r;
+        }
+        a
+    }
+
+    /// The number of bits needed to write the absolute value.
+    pub fn bits(&self) -> u64 {
+        match self.digits.last() {
+            Some(&top) => self.digits.len() as u64 * 32 - top.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.digits.len() {
+            0 => Some(0),
+            1 => Some(self.digits[0] as u64),
+            2 => Some(self.digits[0] as u64 | (self.digits[1] as u64) << 32),
+            _ => None,
+        }
+    }
+
+    pub fn to_string_radix(&self, radix: u32) -> String {
+        if self.is_zero() {
+            return "0".to_owned();
+        }
+        let mut digits = self.digits.clone();
+        let mut result = Vec::new();
+        while !digits.is_empty() {
+            result.push(std::char::from_digit(div_small(&mut digits, radix), radix).unwrap());
+        }
+        if self.negative {
+            result.push('-');
+        }
+        result.iter().rev().cloned().collect()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self.digits.iter().rev().fold(0.0, |acc, &d| acc * 4294967296.0 + d as f64);
+        if self.negative { -magnitude } else { magnitude }
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        let mut digits = self.digits.clone();
+        let mut chunks = Vec::new();
+        while !digits.is_empty() {
+            chunks.push(div_small(&mut digits, 1000000000));
+        }
+        if self.negative {
+            try!(write!(f, "-"));
+        }
+        try!(write!(f, "{}", chunks.pop().unwrap()));
+        for chunk in chunks.iter().rev() {
+            try!(write!(f, "{:09}", chunk));
+        }
+        Ok(())
+    }
+}
+
+/// An exact fraction, kept in lowest terms with a positive denominator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rational {
+    numerator: BigInt,
+    denominator: BigInt,
+}
+
+impl Rational {
+    /// Build a normalized fraction. The denominator must not be zero.
+    pub fn new(numerator: BigInt, denominator: BigInt) -> Self {
+        let (numerator, denominator) = if denominator.is_negative() {
+            (numerator.neg(), denominator.neg())
+        } else {
+            (numerator, denominator)
+        };
+        if denominator.is_one() {
+            return Rational { numerator: numerator, denominator: denominator };
+        }
+        let gcd = numerator.gcd(&denominator);
+        if gcd.is_one() || gcd.is_zero() {
+            Rational { numerator: numerator, denominator: denominator }
+        } else {
+            Rational {
+                numerator: numerator.div_rem(&gcd).0,
+                denominator: denominator.div_rem(&gcd).0,
+            }
+        }
+    }
+
+    pub fn from_integer(n: BigInt) -> Self {
+        Rational { numerator: n, denominator: BigInt::from_u64(1) }
+    }
+
+    /// Parse a decimal literal such as `12`, `0.1` or `.5` exactly.
+    pub fn parse_decimal(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(2, '.');
+        let int_part = parts.next().unwrap_or("");
+        let frac_part = parts.next().unwrap_or("");
+        if int_part.is_empty() && frac_part.is_empty() {
+            return None;
+        }
+        BigInt::parse_radix(&[int_part, frac_part].concat(), 10).map(|numerator| {
+            Rational::new(numerator, BigInt::from_u64(10).pow(frac_part.len() as u64))
+        })
+    }
+
+    pub fn numerator(&self) -> &BigInt {
+        &self.numerator
+    }
+
+    pub fn denominator(&self) -> &BigInt {
+        &self.denominator
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numerator.is_zero()
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denominator.is_one()
+    }
+
+    pub fn neg(&self) -> Self {
+        Rational { numerator: self.numerator.neg(), denominator: self.denominator.clone() }
+    }
+
+    pub fn add(&self, other: &Rational) -> Self {
+        Rational::new(self.numerator.mul(&other.denominator).add(&other.numerator.mul(&self.denominator)),
+                      self.denominator.mul(&other.denominator))
+    }
+
+    pub fn sub(&self, other: &Rational) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Rational) -> Self {
+        Rational::new(self.numerator.mul(&other.numerator), self.denominator.mul(&other.denominator))
+    }
+
+    /// Divide, or `None` when dividing by zero.
+    pub fn div(&self, other: &Rational) -> Option<Self> {
+        if other.is_zero() {
+            None
+        } else {
+            Some(Rational::new(self.numerator.mul(&other.denominator), self.denominator.mul(&other.numerator)))
+        }
+    }
+
+    pub fn compare(&self, other: &Rational) -> Ordering {
+        let difference = self.sub(other);
+        if difference.is_zero() {
+            Ordering::Equal
+        } else if difference.numerator.is_negative() {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.numerator.to_f64() / self.denominator.to_f64()
+    }
+}
+
+impl fmt::Display for Rational {
+    /// Integers and terminating fractions are written in decimal, anything else as `n/d`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_integer() {
+            return write!(f, "{}", self.numerator);
+        }
+
+        let two = BigInt::from_u64(2);
+        let five = BigInt::from_u64(5);
+        let mut rest = self.denominator.clone();
+        let (mut twos, mut fives) = (0u64, 0u64);
+        while rest.is_even() {
+            rest = rest.div_rem(&two).0;
+            twos += 1;
+        }
+        while rest.div_rem(&five).1.is_zero() {
+            rest = rest.div_rem(&five).0;
+            fives += 1;
+        }
+        if !rest.is_one() {
+            return write!(f, "{}/{}", self.numerator, self.denominator);
+        }
+
+        let places = if twos > fives { twos } else { fives };
+        let scaled = self.numerator.abs()
+                         .mul(&BigInt::from_u64(10).pow(places))
+                         .div_rem(&self.denominator).0
+                         .to_string();
+        let scaled = if scaled.len() <= places as usize {
+            [&"0".repeat(places as usize + 1 - scaled.len()), scaled.as_str()].concat()
+        } else {
+            scaled
+        };
+        let (int_part, frac_part) = scaled.split_at(scaled.len() - places as usize);
+        write!(f, "{}{}.{}", if self.numerator.is_negative() { "-" } else { "" }, int_part, frac_part)
+    }
+}
+
+/// A complex number in rectangular form.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re: re, im: im }
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.re == 0.0 && self.im == 0.0
+    }
+
+    pub fn neg(self) -> Self {
+        Complex::new(-self.re, -self.im)
+    }
+
+    pub fn conj(self) -> Self {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn add(self, other: Complex) -> Self {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+
+    pub fn sub(self, other: Complex) -> Self {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+
+    pub fn mul(self, other: Complex) -> Self {
+        Complex::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re)
+    }
+
+    pub fn div(self, other: Complex) -> Self {
+        let d = other.re * other.re + other.im * other.im;
+        Complex::new((self.re * other.re + self.im * other.im) / d, (self.im * other.re - self.re * other.im) / d)
+    }
+
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn exp(self) -> Self {
+        let r = self.re.exp();
+        Complex::new(r * self.im.cos(), r * self.im.sin())
+    }
+
+    /// The principal value of the natural logarithm.
+    pub fn ln(self) -> Self {
+        Complex::new(self.abs().ln(), self.arg())
+    }
+
+    /// The principal square root, computed so that `sqrt(-1)` is exactly `i`.
+    pub fn sqrt(self) -> Self {
+        let r = self.abs();
+        let re = ((r + self.re) / 2.0).sqrt();
+        let im = ((r - self.re) / 2.0).sqrt();
+        Complex::new(re, if self.im.is_sign_negative() { -im } else { im })
+    }
+
+    /// Raise to a power. Integer powers are computed by repeated multiplication, so that `i^2`
+    /// is exactly -1; other powers take the principal value.
+    pub fn pow(self, exponent: Complex) -> Self {
+        if exponent.im == 0.0 && exponent.re.fract() == 0.0 && exponent.re.abs() <= 65536.0 {
+            let mut result = Complex::new(1.0, 0.0);
+            let mut base = self;
+            let mut n = exponent.re.abs() as u32;
+            while n > 0 {
+                if n & 1 == 1 {
+                    result = result.mul(base);
+                }
+                base = base.mul(base);
+                n >>= 1;
+            }
+            return if exponent.re < 0.0 { Complex::new(1.0, 0.0).div(result) } else { result };
+        }
+        if self.is_zero() {
+            return self;
+        }
+        exponent.mul(self.ln()).exp()
+    }
+}
+
+impl Complex {
+    /// Write the number as `a+bi`, leaving out a zero real part and a unit imaginary part, with
+    /// `real` formatting each part.
+    pub fn format<F: Fn(f64) -> String>(&self, real: F) -> String {
+        let negative = self.im.is_sign_negative();
+        let im = self.im.abs();
+        let magnitude = if im == 1.0 { String::new() } else { real(im) };
+        if self.re == 0.0 {
+            [if negative { "-" } else { "" }, &magnitude, "i"].concat()
+        } else {
+            [real(self.re).as_str(), if negative { "-" } else { "+" }, &magnitude, "i"].concat()
+        }
+    }
+}
+
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(|x| x.to_string()))
+    }
+}
@@ -0,0 +1,613 @@
+//! Parsing tokens into expression trees.
+
+use std::cmp;
+use std::fmt;
+
+use super::error::{ErrorKind, ParseError};
+use super::token::{Span, SpannedToken, Token};
+
+/// A parsed expression tree, evaluated against an `Environment` by `evaluate`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExprKind {
+    Number(String),
+    Variable(String),
+    Call(String, Vec<Expr>),
+    Unary(UnaryOperator, Box<Expr>),
+    Binary(BinaryOperator, Box<Expr>, Box<Expr>),
+    Quantity(Box<Expr>, String),
+    Convert(Box<Expr>, String),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Percent(Box<Expr>),
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr {
+            kind: kind,
+            span: span,
+        }
+    }
+
+    // The number of nodes on the longest path from this expression down to a leaf
+    fn height(&self) -> usize {
+        1 + match self.kind {
+            ExprKind::Number(_) | ExprKind::Variable(_) => 0,
+            ExprKind::Call(_, ref args) => args.iter().map(Expr::height).max().unwrap_or(0),
+            ExprKind::Unary(_, ref operand) | ExprKind::Quantity(ref operand, _) |
+            ExprKind::Convert(ref operand, _) | ExprKind::Percent(ref operand) => operand.height(),
+            ExprKind::Binary(_, ref lhs, ref rhs) => cmp::max(lhs.height(), rhs.height()),
+            ExprKind::Conditional(ref condition, ref then, ref otherwise) => {
+                cmp::max(condition.height(), cmp::max(then.height(), otherwise.height()))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    BitNot,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    IntDivide,
+    Modulo,
+    Exponent,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+/// A line of input, which is an expression, an assignment of the form `name = expr` or a
+/// function definition of the form `name(params) = expr`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    Expression(Expr),
+    Assignment(String, Expr),
+    Definition(String, Vec<String>, Expr),
+}
+
+// Prefix operators bind tighter than everything except exponentiation, so -2^2 is -4
+const UNARY_PRECEDENCE: u8 = 10;
+
+fn binary_operator(token: &Token) -> Option<BinaryOperator> {
+    match *token {
+        Token::BitOr      => Some(BinaryOperator::BitOr),
+        Token::BitXor     => Some(BinaryOperator::BitXor),
+        Token::BitAnd     => Some(BinaryOperator::BitAnd),
+        Token::ShiftLeft  => Some(BinaryOperator::ShiftLeft),
+        Token::ShiftRight => Some(BinaryOperator::ShiftRight),
+        Token::Plus       => Some(BinaryOperator::Add),
+        Token::Minus      => Some(BinaryOperator::Subtract),
+        Token::Multiply   => Some(BinaryOperator::Multiply),
+        Token::Divide     => Some(BinaryOperator::Divide),
+        Token::IntDivide  => Some(BinaryOperator::IntDivide),
+        Token::Modulo     => Some(BinaryOperator::Modulo),
+        Token::Exponent   => Some(BinaryOperator::Exponent),
+        Token::Equal        => Some(BinaryOperator::Equal),
+        Token::NotEqual     => Some(BinaryOperator::NotEqual),
+        Token::Less         => Some(BinaryOperator::Less),
+        Token::LessEqual    => Some(BinaryOperator::LessEqual),
+        Token::Greater      => Some(BinaryOperator::Greater),
+        Token::GreaterEqual => Some(BinaryOperator::GreaterEqual),
+        Token::And          => Some(BinaryOperator::And),
+        Token::Or           => Some(BinaryOperator::Or),
+        _ => None,
+    }
+}
+
+// Whether a token can begin an operand. Such a token directly after a value multiplies it, as in
+// `2(3 + 4)` or `3pi`.
+fn starts_operand(token: &Token) -> bool {
+    match *token {
+        Token::Number(_) | Token::Identifier(_) | Token::OpenParen | Token::Not | Token::BitNot => true,
+        _ => false,
+    }
+}
+
+// Whether a `%` followed by this token, or by nothing, is a percentage rather than a modulo. Only
+// tokens which cannot begin an operand count, and signs are left to `Parser::percentage`.
+fn ends_percentage(token: Option<&Token>) -> bool {
+    match token {
+        None => true,
+        Some(&Token::CloseParen) | Some(&Token::Comma) | Some(&Token::In) |
+        Some(&Token::Question) | Some(&Token::Colon) => true,
+        Some(token) => binary_operator(token).is_some() && !starts_operand(token),
+    }
+}
+
+impl BinaryOperator {
+    /// The precedence of the operator, and whether it is right associative. Comparisons bind looser
+    /// than arithmetic and bitwise operators, so `x & 1 == 0` tests the low bit.
+    pub fn precedence(self) -> (u8, bool) {
+        match self {
+            BinaryOperator::Or           => (1, false),
+            BinaryOperator::And          => (2, false),
+            BinaryOperator::Equal        => (3, false),
+            BinaryOperator::NotEqual     => (3, false),
+            BinaryOperator::Less         => (3, false),
+            BinaryOperator::LessEqual    => (3, false),
+            BinaryOperator::Greater      => (3, false),
+            BinaryOperator::GreaterEqual => (3, false),
+            BinaryOperator::BitOr        => (4, false),
+            BinaryOperator::BitXor       => (5, false),
+            BinaryOperator::BitAnd       => (6, false),
+            BinaryOperator::ShiftLeft    => (7, false),
+            BinaryOperator::ShiftRight   => (7, false),
+            BinaryOperator::Add          => (8, false),
+            BinaryOperator::Subtract     => (8, false),
+            BinaryOperator::Multiply     => (9, false),
+            BinaryOperator::Divide       => (9, false),
+            BinaryOperator::IntDivide    => (9, false),
+            BinaryOperator::Modulo       => (9, false),
+            BinaryOperator::Exponent     => (11, true),
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOperator::BitOr      => "|",
+            BinaryOperator::BitXor     => "xor",
+            BinaryOperator::BitAnd     => "&",
+            BinaryOperator::ShiftLeft  => "<<",
+            BinaryOperator::ShiftRight => ">>",
+            BinaryOperator::Add        => "+",
+            BinaryOperator::Subtract   => "-",
+            BinaryOperator::Multiply   => "*",
+            BinaryOperator::Divide     => "/",
+            BinaryOperator::IntDivide  => "//",
+            BinaryOperator::Modulo     => "%",
+            BinaryOperator::Exponent   => "^",
+            BinaryOperator::Equal        => "==",
+            BinaryOperator::NotEqual     => "!=",
+            BinaryOperator::Less         => "<",
+            BinaryOperator::LessEqual    => "<=",
+            BinaryOperator::Greater      => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::And          => "&&",
+            BinaryOperator::Or           => "||",
+        }
+    }
+}
+
+impl Expr {
+    // How tightly the expression binds when printed, to decide where parentheses are needed
+    fn precedence(&self) -> u8 {
+        match self.kind {
+            ExprKind::Binary(operator, _, _) => operator.precedence().0,
+            ExprKind::Unary(_, _) | ExprKind::Percent(_) => UNARY_PRECEDENCE,
+            ExprKind::Convert(_, _) | ExprKind::Conditional(_, _, _) => 0,
+            _ => u8::max_value(),
+        }
+    }
+}
+
+// Print an expression, in parentheses if it binds looser than its context requires
+fn write_operand(f: &mut fmt::Formatter, expr: &Expr, parenthesize: bool) -> fmt::Result {
+    if parenthesize {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+/// Expressions print in a form which parses back to the same tree.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ExprKind::Number(ref n) => write!(f, "{}", n),
+            ExprKind::Variable(ref name) => write!(f, "{}", name),
+            ExprKind::Call(ref name, ref args) => {
+                try!(write!(f, "{}(", name));
+                for (i, arg) in args.iter().enumerate() {
+                    try!(write!(f, "{}{}", if i == 0 { "" } else { ", " }, arg));
+                }
+                write!(f, ")")
+            }
+            ExprKind::Unary(operator, ref operand) => {
+                try!(write!(f, "{}", match operator {
+                    UnaryOperator::Negate => "-",
+                    UnaryOperator::BitNot => "~",
+                    UnaryOperator::Not => "!",
+                }));
+                write_operand(f, operand, operand.precedence() < UNARY_PRECEDENCE)
+            }
+            ExprKind::Binary(operator, ref lhs, ref rhs) => {
+                let (precedence, right_associative) = operator.precedence();
+                try!(write_operand(f, lhs, lhs.precedence() < precedence ||
+                                           (right_associative && lhs.precedence() == precedence)));
+                if operator == BinaryOperator::Exponent {
+                    try!(write!(f, "^"));
+                } else {
+                    try!(write!(f, " {} ", operator.symbol()));
+                }
+                write_operand(f, rhs, rhs.precedence() < precedence ||
+                                      (!right_associative && rhs.precedence() == precedence))
+            }
+            ExprKind::Quantity(ref number, ref unit) => write!(f, "{}{}", number, unit),
+            ExprKind::Convert(ref operand, ref unit) => {
+                try!(write_operand(f, operand, is_conditional(operand)));
+                write!(f, " in {}", unit)
+            }
+            ExprKind::Conditional(ref condition, ref then, ref otherwise) => {
+                try!(write_operand(f, condition, is_conditional(condition)));
+                try!(write!(f, " ? "));
+                // Outside parentheses, a colon in the first branch would end it, as in `a ? 10:20`
+                try!(write_operand(f, then, then.to_string().contains(':')));
+                write!(f, " : {}", otherwise)
+            }
+            ExprKind::Percent(ref operand) => {
+                let prefixed = match operand.kind {
+                    ExprKind::Unary(_, _) => true,
+                    _ => false,
+                };
+                try!(write_operand(f, operand, prefixed || operand.precedence() < UNARY_PRECEDENCE));
+                write!(f, "%")
+            }
+        }
+    }
+}
+
+fn is_conditional(expr: &Expr) -> bool {
+    match expr.kind {
+        ExprKind::Conditional(_, _, _) => true,
+        _ => false,
+    }
+}
+
+// How deeply brackets, unary operators and chains of binary operators may nest, so that parsing,
+// evaluating or printing the tree cannot overflow the stack
+const MAX_NESTING_DEPTH: usize = 128;
+
+/// A precedence climbing parser over a list of tokens.
+pub struct Parser<'a> {
+    tokens: &'a [SpannedToken],
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [SpannedToken]) -> Self {
+        Parser {
+            tokens: tokens,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    /// Parse the whole token list as a single statement.
+    pub fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        let defines = self.tokens.iter().any(|t| match t.token { Token::Assign => true, _ => false });
+        let statement = match (self.tokens.get(0).map(|t| &t.token), self.tokens.get(1).map(|t| &t.token)) {
+            (Some(&Token::Identifier(ref name)), Some(&Token::Assign)) => {
+                self.position = 2;
+                Statement::Assignment(name.clone(), try!(self.parse_expr(0)))
+            }
+            (Some(&Token::Identifier(ref name)), Some(&Token::OpenParen)) if defines => {
+                self.position = 2;
+                let params = try!(self.parse_parameters());
+                Statement::Definition(name.clone(), params, try!(self.parse_expr(0)))
+            }
+            _ => Statement::Expression(try!(self.parse_expr(0))),
+        };
+
+        if self.position < self.tokens.len() {
+            Err(self.unexpected("operator"))
+        } else {
+            Ok(statement)
+        }
+    }
+
+    /// Parse an expression containing only binary operators which bind at least as tightly as
+    /// `min_precedence`.
+    pub fn parse_expr(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(self.too_deep());
+        }
+        self.depth += 1;
+        let result = self.parse_operators(min_precedence);
+        self.depth -= 1;
+        result
+    }
+
+    // The body of `parse_expr`. Each operator applied to `lhs` makes the tree one level taller,
+    // which counts towards the nesting limit just as brackets do.
+    fn parse_operators(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+        let mut lhs = try!(self.parse_unary());
+        let mut height = lhs.height();
+
+        loop {
+            // A `%` at the end of an operand is a percentage, as in `200 * 15%`
+            if let Some(&Token::Modulo) = self.peek() {
+                if try!(self.percentage()) {
+                    if UNARY_PRECEDENCE < min_precedence {
+                        break;
+                    }
+                    self.position += 1;
+                    let span = lhs.span.to(self.previous_span());
+                    lhs = Expr::new(ExprKind::Percent(Box::new(lhs)), span);
+                    height = try!(self.nest(height + 1));
+                    continue;
+                }
+            }
+
+            let (operator, implicit) = match self.peek() {
+                Some(token) if starts_operand(token) && self.multiplies() => (BinaryOperator::Multiply, true),
+                Some(token) => match binary_operator(token) {
+                    Some(operator) => (operator, false),
+                    None => break,
+                },
+                None => break,
+            };
+            let (precedence, right_associative) = operator.precedence();
+            if precedence < min_precedence {
+                break;
+            }
+            if !implicit {
+                self.position += 1;
+            }
+
+            let next_precedence = if right_associative { precedence } else { precedence + 1 };
+            let rhs = try!(self.parse_expr(next_precedence));
+            height = try!(self.nest(cmp::max(height, rhs.height()) + 1));
+            let span = lhs.span.to(rhs.span);
+            lhs = Expr::new(ExprKind::Binary(operator, Box::new(lhs), Box::new(rhs)), span);
+        }
+
+        // Unit conversion binds loosest of all, so `1h + 30min in s` converts the sum
+        while let Some(&Token::In) = self.peek() {
+            if min_precedence > 0 {
+                break;
+            }
+            self.position += 1;
+
+            let unit = match self.peek() {
+                Some(&Token::Identifier(ref name)) | Some(&Token::Unit(ref name)) => name.clone(),
+                _ => return Err(self.unexpected("unit")),
+            };
+            self.position += 1;
+            let span = lhs.span.to(self.previous_span());
+            lhs = Expr::new(ExprKind::Convert(Box::new(lhs), unit), span);
+            height = try!(self.nest(height + 1));
+        }
+
+        // The ternary operator binds loosest and groups to the right, as in `a ? b : c ? d : e`
+        if let Some(&Token::Question) = self.peek() {
+            if min_precedence > 0 {
+                return Ok(lhs);
+            }
+            self.position += 1;
+
+            let then = try!(self.parse_expr(0));
+            match self.peek() {
+                Some(&Token::Colon) => self.position += 1,
+                _ => return Err(self.unexpected(":")),
+            }
+            let otherwise = try!(self.parse_expr(0));
+            try!(self.nest(cmp::max(height, cmp::max(then.height(), otherwise.height())) + 1));
+            let span = lhs.span.to(otherwise.span);
+            lhs = Expr::new(ExprKind::Conditional(Box::new(lhs), Box::new(then), Box::new(otherwise)), span);
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.span();
+        let operator = match self.peek() {
+            Some(&Token::Minus) => UnaryOperator::Negate,
+            Some(&Token::BitNot) => UnaryOperator::BitNot,
+            Some(&Token::Not) => UnaryOperator::Not,
+            Some(&Token::Plus) => {
+                self.position += 1;
+                return self.parse_expr(UNARY_PRECEDENCE);
+            }
+            _ => return self.parse_primary(),
+        };
+        self.position += 1;
+
+        let operand = try!(self.parse_expr(UNARY_PRECEDENCE));
+        let span = start.to(operand.span);
+        Ok(Expr::new(ExprKind::Unary(operator, Box::new(operand)), span))
+    }
+
+    // Numbers, variables, function calls and parenthesized expressions
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.span();
+        let token = try!(self.peek().ok_or_else(|| ParseError::new(ErrorKind::UnexpectedEndOfInput, start)));
+        match *token {
+            Token::Number(ref n) => {
+                self.position += 1;
+                let number = Expr::new(ExprKind::Number(n.clone()), start);
+                if let Some(&Token::Unit(ref unit)) = self.peek() {
+                    self.position += 1;
+                    Ok(Expr::new(ExprKind::Quantity(Box::new(number), unit.clone()), start.to(self.previous_span())))
+                } else {
+                    Ok(number)
+                }
+            }
+            Token::Identifier(ref name) => {
+                self.position += 1;
+                if let Some(&Token::OpenParen) = self.peek() {
+                    self.position += 1;
+                    let args = try!(self.parse_arguments());
+                    Ok(Expr::new(ExprKind::Call(name.clone(), args), start.to(self.previous_span())))
+                } else {
+                    Ok(Expr::new(ExprKind::Variable(name.clone()), start))
+                }
+            }
+            Token::OpenParen => {
+                self.position += 1;
+                let expr = try!(self.parse_expr(0));
+                try!(self.expect_close_paren());
+                Ok(Expr::new(expr.kind, start.to(self.previous_span())))
+            }
+            _ => Err(self.unexpected("number")),
+        }
+    }
+
+    // The parameter names of a function definition, up to and including the `=`
+    fn parse_parameters(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut params: Vec<String> = Vec::new();
+        if let Some(&Token::CloseParen) = self.peek() {
+            self.position += 1;
+        } else {
+            loop {
+                match self.peek() {
+                    Some(&Token::Identifier(ref name)) if params.contains(name) => {
+                        let kind = ErrorKind::OtherError(["Duplicate parameter: ", name].concat());
+                        return Err(ParseError::new(kind, self.span()));
+                    }
+                    Some(&Token::Identifier(ref name)) => params.push(name.clone()),
+                    _ => return Err(self.unexpected("parameter")),
+                }
+                self.position += 1;
+                match self.peek() {
+                    Some(&Token::Comma) => self.position += 1,
+                    _ => {
+                        try!(self.expect_close_paren());
+                        break;
+                    }
+                }
+            }
+        }
+
+        match self.peek() {
+            Some(&Token::Assign) => {
+                self.position += 1;
+                Ok(params)
+            }
+            _ => Err(self.unexpected("=")),
+        }
+    }
+
+    // Function arguments, up to and including the closing parenthesis
+    fn parse_arguments(&mut self) -> Result<Vec<Expr>, ParseError> {
+        let mut args = Vec::new();
+        if let Some(&Token::CloseParen) = self.peek() {
+            self.position += 1;
+            return Ok(args);
+        }
+
+        loop {
+            args.push(try!(self.parse_expr(0)));
+            match self.peek() {
+                Some(&Token::Comma) => self.position += 1,
+                _ => {
+                    try!(self.expect_close_paren());
+                    return Ok(args);
+                }
+            }
+        }
+    }
+
+    fn expect_close_paren(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(&Token::CloseParen) => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(_) => Err(self.unexpected(")")),
+            None => Err(ParseError::new(ErrorKind::OtherError("No matching close parenthesis found.".to_owned()),
+                                        self.span())),
+        }
+    }
+
+    // Whether the `%` at the current token is a percentage rather than a modulo. A `+` or `-` after
+    // it is a binary operator when the `%` is written against its operand, as in `10% - 1`, and the
+    // sign of the right operand of a modulo when it is written against that, as in `7 % -4`.
+    // Spaced out on both sides, as in `7 % - 4`, it could be either and is refused.
+    fn percentage(&self) -> Result<bool, ParseError> {
+        let (sign, symbol) = match self.tokens.get(self.position + 1) {
+            Some(sign @ &SpannedToken { token: Token::Plus, .. }) => (sign, "+"),
+            Some(sign @ &SpannedToken { token: Token::Minus, .. }) => (sign, "-"),
+            next => return Ok(ends_percentage(next.map(|t| &t.token))),
+        };
+        let attached = |before: usize| {
+            match (self.tokens.get(before), self.tokens.get(before + 1)) {
+                (Some(a), Some(b)) => a.span.end == b.span.start,
+                _ => false,
+            }
+        };
+        if attached(self.position - 1) {
+            Ok(true)
+        } else if attached(self.position + 1) {
+            Ok(false)
+        } else {
+            let message = format!("Ambiguous %: write `a% {0} b` for a percentage or `a % {0}b` for a modulo",
+                                  symbol);
+            Err(ParseError::new(ErrorKind::OtherError(message), self.span().to(sign.span)))
+        }
+    }
+
+    // Whether an operand starting at the current token multiplies the one before it. Two numbers
+    // side by side, as in `2 3` or `1..2`, are more likely a mistake than a product, and neither is
+    // a number ending in a decimal point followed by anything.
+    fn multiplies(&self) -> bool {
+        match (&self.tokens[self.position - 1].token, self.peek()) {
+            (&Token::Number(_), Some(&Token::Number(_))) => false,
+            (&Token::Number(ref n), _) => !n.ends_with('.'),
+            _ => true,
+        }
+    }
+
+    // Check the height of a tree built at the current depth against the nesting limit
+    fn nest(&self, height: usize) -> Result<usize, ParseError> {
+        if self.depth + height > MAX_NESTING_DEPTH {
+            Err(self.too_deep())
+        } else {
+            Ok(height)
+        }
+    }
+
+    fn too_deep(&self) -> ParseError {
+        ParseError::new(ErrorKind::OtherError("Expression nested too deeply".to_owned()), self.span())
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position).map(|t| &t.token)
+    }
+
+    // The span of the current token, or an empty span after the last token at the end of input
+    fn span(&self) -> Span {
+        match self.tokens.get(self.position) {
+            Some(t) => t.span,
+            None => {
+                let end = self.tokens.last().map_or(0, |t| t.span.end);
+                Span::new(end, end)
+            }
+        }
+    }
+
+    fn previous_span(&self) -> Span {
+        self.tokens[self.position - 1].span
+    }
+
+    fn unexpected(&self, expected: &'static str) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError::new(ErrorKind::UnexpectedToken(token.to_string(), expected), self.span()),
+            None => ParseError::new(ErrorKind::UnexpectedEndOfInput, self.span()),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Randomized tests. Generated expression trees must print in a form which parses back to the same
+//! tree and evaluates to the same result as a reference evaluator, and arbitrary input must never
+//! make the tokenizer, parser or evaluator panic.
+
+use std::panic;
+
+use super::*;
+use super::test::render;
+
+// How many random cases each test tries
+const CASES: usize = 5000;
+
+// A xorshift generator, so that failures can be reproduced and no extra dependencies are needed
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+static LITERALS: &'static [&'static str] = &["0", "1", "2", "7", "42", "0.5", "1_000", "0x1f", "17:45", "2026-10-17"];
+static NAMES: &'static [&'static str] = &["x", "y", "ans", "pi"];
+static FUNCTIONS: &'static [&'static str] = &["sqrt", "max", "f"];
+static UNITS: &'static [&'static str] = &["m", "KiB", "min", "s"];
+
+static UNARY: [UnaryOperator; 3] = [UnaryOperator::Negate, UnaryOperator::BitNot, UnaryOperator::Not];
+
+static BINARY: [BinaryOperator; 20] = [
+    BinaryOperator::Add, BinaryOperator::Subtract, BinaryOperator::Multiply, BinaryOperator::Divide,
+    BinaryOperator::IntDivide, BinaryOperator::Modulo, BinaryOperator::Exponent, BinaryOperator::BitAnd,
+    BinaryOperator::BitOr, BinaryOperator::BitXor, BinaryOperator::ShiftLeft, BinaryOperator::ShiftRight,
+    BinaryOperator::Equal, BinaryOperator::NotEqual, BinaryOperator::Less, BinaryOperator::LessEqual,
+    BinaryOperator::Greater, BinaryOperator::GreaterEqual, BinaryOperator::And, BinaryOperator::Or,
+];
+
+static ARITHMETIC: [BinaryOperator; 5] = [
+    BinaryOperator::Add, BinaryOperator::Subtract, BinaryOperator::Multiply, BinaryOperator::Divide,
+    BinaryOperator::Exponent,
+];
+
+// Spans play no part in these tests
+fn node(kind: ExprKind) -> Expr {
+    Expr::new(kind, Span::new(0, 0))
+}
+
+// Any expression tree the parser can produce
+fn expression(random: &mut Random, depth: usize) -> Expr {
+    if depth == 0 || random.below(4) == 0 {
+        return match random.below(3) {
+            0 => node(ExprKind::Number(random.choose(LITERALS).to_string())),
+            1 => node(ExprKind::Variable(random.choose(NAMES).to_string())),
+            _ => {
+                let number = node(ExprKind::Number(random.choose(&["2", "42", "0.5"]).to_string()));
+                node(ExprKind::Quantity(Box::new(number), random.choose(UNITS).to_string()))
+            }
+        };
+    }
+
+    let operand = |random: &mut Random| Box::new(expression(random, depth - 1));
+    match random.below(8) {
+        0 => node(ExprKind::Unary(*random.choose(&UNARY), operand(random))),
+        1 => {
+            let args = (0..random.below(3) + 1).map(|_| expression(random, depth - 1)).collect();
+            node(ExprKind::Call(random.choose(FUNCTIONS).to_string(), args))
+        }
+        2 => node(ExprKind::Convert(operand(random), random.choose(UNITS).to_string())),
+        3 => node(ExprKind::Conditional(operand(random), operand(random), operand(random))),
+        4 => node(ExprKind::Percent(operand(random))),
+        _ => node(ExprKind::Binary(*random.choose(&BINARY), operand(random), operand(random))),
+    }
+}
+
+// An expression using only the operators `reference` understands
+fn arithmetic(random: &mut Random, depth: usize) -> Expr {
+    if depth == 0 || random.below(4) == 0 {
+        return node(ExprKind::Number(random.choose(&["1", "2", "3", "7", "0.5", "10"]).to_string()));
+    }
+    if random.below(6) == 0 {
+        return node(ExprKind::Unary(UnaryOperator::Negate, Box::new(arithmetic(random, depth - 1))));
+    }
+    let lhs = Box::new(arithmetic(random, depth - 1));
+    let rhs = Box::new(arithmetic(random, depth - 1));
+    node(ExprKind::Binary(*random.choose(&ARITHMETIC), lhs, rhs))
+}
+
+// Evaluate an arithmetic expression tree directly in floating point. Gives up with `None` on
+// division by zero, which is an error rather than an infinity, on overflow, and on negative
+// numbers raised to fractional powers, which are complex rather than NaN.
+fn reference(expr: &Expr) -> Option<f64> {
+    match expr.kind {
+        ExprKind::Number(ref n) => n.parse().ok(),
+        ExprKind::Unary(UnaryOperator::Negate, ref operand) => reference(operand).map(|x| -x),
+        ExprKind::Binary(operator, ref lhs, ref rhs) => {
+            let (a, b) = match (reference(lhs), reference(rhs)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return None,
+            };
+            let result = match operator {
+                BinaryOperator::Add => a + b,
+                BinaryOperator::Subtract => a - b,
+                BinaryOperator::Multiply => a * b,
+                BinaryOperator::Divide if b == 0.0 => return None,
+                BinaryOperator::Divide => a / b,
+                BinaryOperator::Exponent => a.powf(b),
+                _ => unreachable!(),
+            };
+            if result.is_finite() { Some(result) } else { None }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn parse_expression(input: &str) -> Expr {
+    let tokens = tokenize(input).unwrap_or_else(|e| panic!("{:?} did not tokenize: {}", input, e));
+    match Parser::new(&tokens).parse_statement() {
+        Ok(Statement::Expression(expr)) => expr,
+        other => panic!("{:?} did not parse as an expression: {:?}", input, other),
+    }
+}
+
+#[test]
+fn printed_trees_parse_back() {
+    let mut random = Random(0x2545f4914f6cdd1d);
+    for _ in 0..CASES {
+        let expr = expression(&mut random, 5);
+        let input = expr.to_string();
+        assert_eq!(render(&parse_expression(&input)), render(&expr), "for {:?}", input);
+    }
+}
+
+#[test]
+fn evaluation_matches_reference() {
+    let mut random = Random(0x9e3779b97f4a7c15);
+    for _ in 0..CASES {
+        let expr = arithmetic(&mut random, 6);
+        let expected = match reference(&expr) {
+            Some(expected) => expected,
+            None => continue,
+        };
+
+        let input = expr.to_string();
+        let actual = match Evaluator::new().evaluate(&input) {
+            Ok(value) => value.to_f64(),
+            Err(e) => panic!("{:?} failed: {}", input, e),
+        };
+        let tolerance = 1e-9 * expected.abs().max(1.0);
+        assert!((actual - expected).abs() <= tolerance, "{:?} gave {} but expected {}", input, actual, expected);
+    }
+}
+
+#[test]
+fn signs_after_percent_signs() {
+    // These print without extra parentheses, so the round trip above relies on the parser alone
+    let cases = [
+        ("a % -b", "(Modulo a (Negate b))"),
+        ("a % ~b - c", "(Subtract (Modulo a (BitNot b)) c)"),
+        ("a% - b", "(Subtract (Percent a) b)"),
+        ("a + b% - c", "(Subtract (Add a (Percent b)) c)"),
+        ("a * b% / c", "(Divide (Multiply a (Percent b)) c)"),
+    ];
+    for &(input, tree) in &cases {
+        let expr = parse_expression(input);
+        assert_eq!(render(&expr), tree, "for {:?}", input);
+        assert_eq!(expr.to_string(), input);
+    }
+}
+
+static FRAGMENTS: &'static [&'static str] = &[
+    "0", "1", "9", "12", "0.5", ".", "_", "0x", "0b", "1e", "2026-10-17", "17:45", "T",
+    "+", "-", "*", "/", "//", "%", "^", "(", ")", ",", "=", "==", "!=", "<", "<=", "<<", ">>",
+    "&", "&&", "|", "||", "~", "!", "?", ":", "xor", "in",
+    "x", "f", "pi", "i", "ans", "now", "true", "sqrt(", "max(", "m", "KiB", "d", "h", "s", "min",
+    " ", "é", "$", "#",
+];
+
+// Fragments which nest when repeated, to check that deep input is refused rather than overflowing
+// the stack. Runs as long as a fuzzer's inputs are mixed in with the other fragments.
+static NESTING: &'static [&'static str] = &["(", "-", "~", "!", "2^", "sqrt(", "1+", "x%", "1 in "];
+
+#[test]
+fn arbitrary_input_never_panics() {
+    let mut random = Random(0xd1b54a32d192ed03);
+    for _ in 0..CASES {
+        let input: String = (0..random.below(12) + 1).map(|_| {
+            if random.below(10) == 0 {
+                let length = random.below(2000) + 1;
+                random.choose(NESTING).repeat(length)
+            } else {
+                random.choose(FRAGMENTS).to_string()
+            }
+        }).collect();
+        let result = panic::catch_unwind(|| {
+            let mut evaluator = Evaluator::new();
+            let _ = evaluator.run("f(x) = x^2");
+            let _ = evaluator.run(&input);
+        });
+        assert!(result.is_ok(), "panicked on {:?}", input);
+    }
+}
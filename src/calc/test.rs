@@ -0,0 +1,762 @@
+use super::*;
+
+#[test]
+fn simple_addition() {
+    assert_eq!(tokenize("12+3").and_then(parse).unwrap(), "15");
+}
+
+#[test]
+fn addition() {
+    assert_eq!(tokenize("12+3+5").and_then(parse).unwrap(), "20");
+}
+
+#[test]
+fn simple_subtraction() {
+    assert_eq!(tokenize("12-3").and_then(parse).unwrap(), "9");
+}
+
+#[test]
+fn subtraction() {
+    assert_eq!(tokenize("12-3-4").and_then(parse).unwrap(), "5");
+}
+
+#[test]
+fn mixed_addition_and_subtraction() {
+    assert_eq!(tokenize("12+3-4+8-2-3").and_then(parse).unwrap(), "14");
+}
+
+#[test]
+fn simple_parentheses() {
+    assert_eq!(tokenize("((3))").and_then(parse).unwrap(), "3");
+    assert_eq!(tokenize("(12+(2+3))").and_then(parse).unwrap(), "17");
+    assert_eq!(tokenize("12+(2+3)").and_then(parse).unwrap(), "17");
+}
+
+#[test]
+fn parentheses() {
+    assert_eq!(tokenize("12+(2+(3+5))+4+(((6)))").and_then(parse).unwrap(), "32");
+}
+
+#[test]
+fn multiplication() {
+    assert_eq!(tokenize("3*3").and_then(parse).unwrap(), "9");
+    assert_eq!(tokenize("3*5").and_then(parse).unwrap(), "15");
+    assert_eq!(tokenize("0*5").and_then(parse).unwrap(), "0");
+    assert_eq!(tokenize("5*4*3*2*1").and_then(parse).unwrap(), "120");
+    assert_eq!(tokenize("(5*4)*3*(2*1)").and_then(parse).unwrap(), "120");
+}
+
+#[test]
+fn division() {
+    assert_eq!(tokenize("12/4").and_then(parse).unwrap(), "3");
+    assert_eq!(tokenize("12/3").and_then(parse).unwrap(), "4");
+    assert_eq!(tokenize("5/2").and_then(parse).unwrap(), "2.5");
+    assert_eq!(tokenize("120/5/4/3/2").and_then(parse).unwrap(), "1");
+    assert_eq!(tokenize("(120/5)/4/(3/2)").and_then(parse).unwrap(), "4");
+}
+
+#[test]
+fn exponentiation() {
+    assert_eq!(tokenize("3^2").and_then(parse).unwrap(), "9");
+    assert_eq!(tokenize("2^3^2").and_then(parse).unwrap(), "512");
+    assert_eq!(tokenize("2^(2+1)^2").and_then(parse).unwrap(), "512");
+}
+
+#[test]
+fn variables() {
+    let mut env = Environment::new();
+    assert_eq!(tokenize("x = 3*4").and_then(|t| parse_with_env(t, &mut env)).unwrap(), "12");
+    assert_eq!(tokenize("x_2 = x+1").and_then(|t| parse_with_env(t, &mut env)).unwrap(), "13");
+    assert_eq!(tokenize("x*x_2 - -x").and_then(|t| parse_with_env(t, &mut env)).unwrap(), "168");
+    assert_eq!(tokenize("ans/2").and_then(|t| parse_with_env(t, &mut env)).unwrap(), "84");
+    match tokenize("y+1").and_then(|t| parse_with_env(t, &mut env)) {
+        Err(ParseError { kind: ErrorKind::UnknownVariable(ref name), .. }) if name == "y" => (),
+        other => panic!("expected unknown variable, got {:?}", other),
+    }
+}
+
+#[test]
+fn functions() {
+    assert_eq!(tokenize("sqrt(16)").and_then(parse).unwrap(), "4");
+    assert_eq!(tokenize("log(8, 2)").and_then(parse).unwrap(), "3");
+    assert_eq!(tokenize("log(1000)").and_then(parse).unwrap(), "3");
+    assert_eq!(tokenize("abs(-3)*2").and_then(parse).unwrap(), "6");
+    assert_eq!(tokenize("-sqrt(abs(-4))*2").and_then(parse).unwrap(), "-4");
+    assert_eq!(tokenize("ln(e)").and_then(parse).unwrap(), "1");
+    assert_eq!(tokenize("cos(pi)").and_then(parse).unwrap(), "-1");
+}
+
+#[test]
+fn function_errors() {
+    match tokenize("sqrt(1, 2)").and_then(parse) {
+        Err(ParseError { kind: ErrorKind::WrongArgumentCount(ref name, 1, 2), .. }) if name == "sqrt" => (),
+        other => panic!("expected wrong argument count, got {:?}", other),
+    }
+    match tokenize("frob(1)").and_then(parse) {
+        Err(ParseError { kind: ErrorKind::UnknownFunction(ref name), .. }) if name == "frob" => (),
+        other => panic!("expected unknown function, got {:?}", other),
+    }
+    assert!(tokenize("sqrt(4").and_then(parse).is_err());
+}
+
+#[test]
+fn user_functions() {
+    let mut env = Environment::new();
+    let mut run = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut env));
+    assert_eq!(run("f(x) = x^2 + 1").unwrap(), "f(x) = x^2 + 1");
+    assert_eq!(run("f(3)").unwrap(), "10");
+    assert_eq!(run("hyp(a, b) = sqrt(a^2 + b^2)").unwrap(), "hyp(a, b) = sqrt(a^2 + b^2)");
+    assert_eq!(run("hyp(3, f(2) - 1)").unwrap(), "5");
+    assert_eq!(run("k = 10").unwrap(), "10");
+    assert_eq!(run("g(x) = x * k").unwrap(), "g(x) = x * k");
+    assert_eq!(run("g(2)").unwrap(), "20");
+    assert_eq!(run("f(x) = 2 * (x - 1)").unwrap(), "f(x) = 2 * (x - 1)");
+    assert_eq!(run("f(4)").unwrap(), "6");
+    assert_eq!(run("three() = 3").unwrap(), "three() = 3");
+    assert_eq!(run("three()").unwrap(), "3");
+}
+
+#[test]
+fn parameter_scoping() {
+    let mut env = Environment::new();
+    let mut run = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut env));
+    run("x = 100").unwrap();
+    run("f(x) = x + 1").unwrap();
+    run("g(y) = f(y) + x").unwrap();
+    assert_eq!(run("f(1)").unwrap(), "2");
+    assert_eq!(run("g(1)").unwrap(), "102");
+    assert_eq!(run("x").unwrap(), "100");
+    run("h(a) = a + b").unwrap();
+    match run("h(1)") {
+        Err(ParseError { kind: ErrorKind::UnknownVariable(ref name), .. }) if name == "b" => (),
+        other => panic!("expected unknown variable, got {:?}", other),
+    }
+    match run("f()") {
+        Err(ParseError { kind: ErrorKind::WrongArgumentCount(ref name, 1, 0), .. }) if name == "f" => (),
+        other => panic!("expected wrong argument count, got {:?}", other),
+    }
+}
+
+#[test]
+fn recursion_limit() {
+    let mut env = Environment::new();
+    let mut run = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut env));
+    run("f(n) = f(n - 1) + 1").unwrap();
+    match run("f(3)") {
+        Err(ParseError { kind: ErrorKind::RecursionLimit(ref name), .. }) if name == "f" => (),
+        other => panic!("expected recursion limit, got {:?}", other),
+    }
+}
+
+#[test]
+fn nesting_limit() {
+    let nested = |open: &str, n: usize, close: &str| [open.repeat(n), "1".to_owned(), close.repeat(n)].concat();
+    assert_eq!(tokenize(&nested("(", 100, ")")).and_then(parse).unwrap(), "1");
+    assert_eq!(tokenize(&nested("-", 100, "")).and_then(parse).unwrap(), "1");
+    assert_eq!(tokenize(&["1", &" + 1".repeat(100)].concat()).and_then(parse).unwrap(), "101");
+    for input in &[nested("(", 100000, ")"), nested("-", 100000, ""), nested("~(-", 5000, ")"),
+                   nested("2^", 100000, ""), ["1", &" + 1".repeat(100000)].concat(),
+                   ["(1", &" + 1".repeat(100), ")", &" * 2".repeat(100)].concat()] {
+        match tokenize(input).and_then(parse) {
+            Err(ParseError { kind: ErrorKind::OtherError(ref message), .. })
+                if message == "Expression nested too deeply" => (),
+            other => panic!("expected a nesting error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn invalid_definitions() {
+    let mut env = Environment::new();
+    let mut run = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut env));
+    assert!(run("sqrt(x) = x").is_err());
+    assert!(run("f(x, x) = x").is_err());
+    assert!(run("f(1) = 2").is_err());
+    assert!(run("f(x) x").is_err());
+    assert!(run("f(x) = ").is_err());
+}
+
+#[test]
+fn exact_arithmetic() {
+    let exact = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut Environment::exact()));
+    assert_eq!(exact("2^64+1").unwrap(), "18446744073709551617");
+    assert_eq!(exact("0.1+0.2").unwrap(), "0.3");
+    assert_eq!(exact("1/3").unwrap(), "1/3");
+    assert_eq!(exact("1/3*3").unwrap(), "1");
+    assert_eq!(exact("-7/8").unwrap(), "-0.875");
+    assert_eq!(exact("2^-2").unwrap(), "0.25");
+    assert_eq!(exact("(2^100)/(2^98)-1").unwrap(), "3");
+    assert_eq!(exact("12345678901234567890*98765432109876543210").unwrap(),
+               "1219326311370217952237463801111263526900");
+    assert_eq!(exact("(10^30+7)/(10^15)").unwrap(), "1000000000000000.000000000000007");
+    assert!(exact("1/0").is_err());
+    assert!(exact("0^-1").is_err());
+    assert!(exact("2^0.5").is_err());
+
+    // Powers too big to hold fail quickly rather than running out of memory
+    assert_eq!(exact("2^65536 - 2^65536").unwrap(), "0");
+    assert_eq!(exact("(1/2)^-65536 == 2^65536").unwrap(), "true");
+    assert_eq!(exact("1^(2^40) + (-1)^(2^40+1) + 0^(2^40)").unwrap(), "0");
+    for input in &["2^(2^40)", "2^65537", "(2/3)^100000", "(-3)^-70000", "10^(10^18)"] {
+        match exact(input) {
+            Err(ParseError { kind: ErrorKind::OtherError(ref message), .. }) if message == "Result too large" => (),
+            other => panic!("expected {} to be too large, got {:?}", input, other),
+        }
+    }
+}
+
+#[test]
+fn radix_literals() {
+    assert_eq!(tokenize("0x1f + 0o17 + 0b1010").and_then(parse).unwrap(), "56");
+    assert_eq!(tokenize("1_000_000 / 1_000.5").and_then(parse).unwrap(), (1000000.0f64 / 1000.5).to_string());
+    assert_eq!(tokenize("0xFFFF_FFFF_FFFF_FFFF + 1")
+                   .and_then(|t| parse_with_env(t, &mut Environment::exact())).unwrap(),
+               "18446744073709551616");
+    assert!(tokenize("0b102").and_then(parse).is_err());
+    assert!(tokenize("0xg").and_then(parse).is_err());
+    assert!(tokenize("0bé + 1").and_then(parse).is_err());
+}
+
+#[test]
+fn output_radix() {
+    let mut env = Environment::new();
+    env.format.set_radix(16).unwrap();
+    assert_eq!(tokenize("255").and_then(|t| parse_with_env(t, &mut env)).unwrap(), "0xff");
+    assert_eq!(tokenize("-0x10").and_then(|t| parse_with_env(t, &mut env)).unwrap(), "-0x10");
+    assert_eq!(tokenize("1/4").and_then(|t| parse_with_env(t, &mut env)).unwrap(), "0.25");
+    env.format.set_radix(2).unwrap();
+    assert_eq!(tokenize("10").and_then(|t| parse_with_env(t, &mut env)).unwrap(), "0b1010");
+    env.format.set_radix(8).unwrap();
+    assert_eq!(tokenize("0").and_then(|t| parse_with_env(t, &mut env)).unwrap(), "0o0");
+    assert!(env.format.set_radix(7).is_err());
+}
+
+#[test]
+fn plain_format() {
+    let format = Format::new();
+    assert_eq!(format.real(0.1 + 0.2), "0.30000000000000004");
+    assert_eq!(format.real(-2.5), "-2.5");
+    assert_eq!(format.real(1e21), "1000000000000000000000");
+    assert_eq!(format.real(std::f64::INFINITY), "inf");
+    assert_eq!(format.value(&Value::Exact(Rational::new(BigInt::from_u64(1), BigInt::from_u64(3)))), "1/3");
+    assert_eq!(format.value(&Value::Bool(true)), "true");
+}
+
+#[test]
+fn precision_format() {
+    let mut format = Format::new();
+    format.precision = Some(3);
+    assert_eq!(format.real(0.1 + 0.2), "0.3");
+    assert_eq!(format.real(2.0f64.sqrt()), "1.414");
+    assert_eq!(format.real(-0.0001), "0");
+    assert_eq!(format.real(1234.0), "1234");
+    assert_eq!(format.value(&Value::Exact(Rational::new(BigInt::from_u64(2), BigInt::from_u64(3)))), "0.667");
+    assert_eq!(format.value(&Value::Complex(Complex::new(1.0 / 3.0, -2.0))), "0.333-2i");
+    format.precision = Some(0);
+    assert_eq!(format.real(2.5001), "3");
+}
+
+#[test]
+fn scientific_format() {
+    let mut format = Format::new();
+    format.notation = Notation::Scientific;
+    assert_eq!(format.real(1234.5), "1.2345e3");
+    assert_eq!(format.real(-0.00012), "-1.2e-4");
+    assert_eq!(format.real(0.0), "0e0");
+    format.precision = Some(2);
+    assert_eq!(format.real(1234.5), "1.23e3");
+    assert_eq!(format.real(9999.0), "1e4");
+    assert_eq!(format.value(&Value::Exact(Rational::from_integer(BigInt::from_u64(5000)))), "5e3");
+}
+
+#[test]
+fn engineering_format() {
+    let mut format = Format::new();
+    format.notation = Notation::Engineering;
+    assert_eq!(format.real(1234.5), "1.2345e3");
+    assert_eq!(format.real(12345.0), "12.345e3");
+    assert_eq!(format.real(100000.0), "100e3");
+    assert_eq!(format.real(-0.00012), "-120e-6");
+    assert_eq!(format.real(0.5), "500e-3");
+    format.precision = Some(2);
+    assert_eq!(format.real(123456.0), "123e3");
+}
+
+#[test]
+fn grouped_format() {
+    let mut format = Format::new();
+    format.group = true;
+    assert_eq!(format.real(1234567.891), "1,234,567.891");
+    assert_eq!(format.real(-999.0), "-999");
+    assert_eq!(format.real(-1000.0), "-1,000");
+    assert_eq!(format.value(&Value::Exact(Rational::new(BigInt::from_u64(1000000), BigInt::from_u64(3)))),
+               "1,000,000/3");
+    format.set_radix(16).unwrap();
+    assert_eq!(format.value(&Value::Float(4294967295.0)), "0xffff_ffff");
+    format.set_radix(2).unwrap();
+    assert_eq!(format.value(&Value::Float(-37.0)), "-0b10_0101");
+}
+
+#[test]
+fn integer_operators() {
+    assert_eq!(tokenize("17 % 5").and_then(parse).unwrap(), "2");
+    assert_eq!(tokenize("-7 % 2").and_then(parse).unwrap(), "1");
+    assert_eq!(tokenize("17 // 5").and_then(parse).unwrap(), "3");
+    assert_eq!(tokenize("-7 // 2").and_then(parse).unwrap(), "-4");
+    assert_eq!(tokenize("2^3 % 5").and_then(parse).unwrap(), "3");
+    assert!(tokenize("7 % 2.5").and_then(parse).is_err());
+    assert!(tokenize("7 // 0").and_then(parse).is_err());
+}
+
+#[test]
+fn bitwise_operators() {
+    assert_eq!(tokenize("6 & 3 | 8").and_then(parse).unwrap(), "10");
+    assert_eq!(tokenize("5 xor 3").and_then(parse).unwrap(), "6");
+    assert_eq!(tokenize("1 | 2 xor 3 & 1").and_then(parse).unwrap(), "3");
+    assert_eq!(tokenize("~0").and_then(parse).unwrap(), "-1");
+    assert_eq!(tokenize("-6 & 7").and_then(parse).unwrap(), "2");
+    assert_eq!(tokenize("-1 xor 0xff").and_then(parse).unwrap(), "-256");
+    assert_eq!(tokenize("1 + 2 << 3").and_then(parse).unwrap(), "24");
+    assert_eq!(tokenize("-9 >> 1").and_then(parse).unwrap(), "-5");
+    assert_eq!(tokenize("1 << 100 >> 99").and_then(parse).unwrap(), "2");
+    assert!(tokenize("1.5 & 1").and_then(parse).is_err());
+    assert!(tokenize("1 << -1").and_then(parse).is_err());
+    assert!(tokenize("1 <<< 2").and_then(parse).is_err());
+}
+
+#[test]
+fn unary_operators() {
+    assert_eq!(tokenize("-(2+3)").and_then(parse).unwrap(), "-5");
+    assert_eq!(tokenize("-2^2").and_then(parse).unwrap(), "-4");
+    assert_eq!(tokenize("2^-1").and_then(parse).unwrap(), "0.5");
+    assert_eq!(tokenize("--3").and_then(parse).unwrap(), "3");
+    assert_eq!(tokenize("+3 - +2").and_then(parse).unwrap(), "1");
+    assert_eq!(tokenize("~-1").and_then(parse).unwrap(), "0");
+    let mut env = Environment::new();
+    tokenize("x = 4").and_then(|t| parse_with_env(t, &mut env)).unwrap();
+    assert_eq!(tokenize("2*-x").and_then(|t| parse_with_env(t, &mut env)).unwrap(), "-8");
+}
+
+pub fn render(expr: &Expr) -> String {
+    match expr.kind {
+        ExprKind::Number(ref n) | ExprKind::Variable(ref n) => n.clone(),
+        ExprKind::Call(ref name, ref args) => {
+            format!("({} {})", name, args.iter().map(render).collect::<Vec<_>>().join(" "))
+        }
+        ExprKind::Unary(operator, ref operand) => format!("({:?} {})", operator, render(operand)),
+        ExprKind::Binary(operator, ref lhs, ref rhs) => format!("({:?} {} {})", operator, render(lhs), render(rhs)),
+        ExprKind::Quantity(ref n, ref unit) => format!("{}{}", render(n), unit),
+        ExprKind::Convert(ref operand, ref unit) => format!("(In {} {})", render(operand), unit),
+        ExprKind::Conditional(ref c, ref a, ref b) => format!("(If {} {} {})", render(c), render(a), render(b)),
+        ExprKind::Percent(ref operand) => format!("(Percent {})", render(operand)),
+    }
+}
+
+#[test]
+fn expression_tree() {
+    let tokens = tokenize("1-2-3^4^-5").unwrap();
+    match Parser::new(&tokens).parse_statement().unwrap() {
+        Statement::Expression(ref expr) => {
+            assert_eq!(render(expr), "(Subtract (Subtract 1 2) (Exponent 3 (Exponent 4 (Negate 5))))");
+            assert_eq!(expr.span, Span::new(0, 10));
+        }
+        other => panic!("expected an expression, got {:?}", other),
+    }
+}
+
+#[test]
+fn comparisons() {
+    let run = |input: &str| tokenize(input).and_then(parse).unwrap();
+    assert_eq!(run("1 < 2"), "true");
+    assert_eq!(run("2 <= 1"), "false");
+    assert_eq!(run("1 + 1 == 2"), "true");
+    assert_eq!(run("0.1 + 0.2 != 0.3"), "true");
+    assert_eq!(run("3 >= 3 && 4 > 5"), "false");
+    assert_eq!(run("!(1 > 2) || 1/0 > 1"), "true");
+    assert_eq!(run("6 & 1 == 0"), "true");
+    assert_eq!(run("true == (1 < 2)"), "true");
+    assert_eq!(run("1h == 60min"), "true");
+    assert_eq!(run("1KiB > 1000B"), "true");
+    assert_eq!(run("asin(2) == asin(2)"), "false");
+    let exact = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut Environment::exact())).unwrap();
+    assert_eq!(exact("0.1 + 0.2 == 0.3"), "true");
+    assert_eq!(exact("1/3 < 0.3334"), "true");
+}
+
+#[test]
+fn conditionals() {
+    let run = |input: &str| tokenize(input).and_then(parse).unwrap();
+    assert_eq!(run("1 < 2 ? 10 : 20"), "10");
+    assert_eq!(run("false ? 1 : true ? 2 : 3"), "2");
+    assert_eq!(run("(1 > 2 ? 1 : 2) * 3"), "6");
+    assert_eq!(run("true ? 1 : 1/0"), "1");
+    let tokens = tokenize("a ? b : c ? d : e").unwrap();
+    match Parser::new(&tokens).parse_statement().unwrap() {
+        Statement::Expression(ref expr) => assert_eq!(render(expr), "(If a b (If c d e))"),
+        other => panic!("expected an expression, got {:?}", other),
+    }
+
+    let mut env = Environment::new();
+    let mut run = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut env));
+    assert_eq!(run("fact(n) = n <= 1 ? 1 : n * fact(n - 1)").unwrap(), "fact(n) = n <= 1 ? 1 : n * fact(n - 1)");
+    assert_eq!(run("fact(10)").unwrap(), "3628800");
+    assert_eq!(run("fib(n) = n < 2 ? n : fib(n - 1) + fib(n - 2)").unwrap(),
+               "fib(n) = n < 2 ? n : fib(n - 1) + fib(n - 2)");
+    assert_eq!(run("fib(15)").unwrap(), "610");
+}
+
+#[test]
+fn boolean_errors() {
+    let kind = |input: &str| tokenize(input).and_then(parse).unwrap_err().kind;
+    match kind("true + 1") {
+        ErrorKind::TypeMismatch("number", "boolean") => (),
+        other => panic!("expected type mismatch, got {:?}", other),
+    }
+    match kind("1 ? 2 : 3") {
+        ErrorKind::TypeMismatch("boolean", "number") => (),
+        other => panic!("expected type mismatch, got {:?}", other),
+    }
+    match kind("1 && true") {
+        ErrorKind::TypeMismatch("boolean", "number") => (),
+        other => panic!("expected type mismatch, got {:?}", other),
+    }
+    assert!(tokenize("true < false").and_then(parse).is_err());
+    assert!(tokenize("true == 1").and_then(parse).is_err());
+    assert!(tokenize("-true").and_then(parse).is_err());
+    assert!(tokenize("sqrt(true)").and_then(parse).is_err());
+    assert!(tokenize("1 < 2 ? 3").and_then(parse).is_err());
+    assert!(tokenize("1s < 2B").and_then(parse).is_err());
+}
+
+#[test]
+fn statistics() {
+    let run = |input: &str| tokenize(input).and_then(parse).unwrap();
+    assert_eq!(run("sum(1, 2, 3, 4)"), "10");
+    assert_eq!(run("mean(1, 2, 3, 4)"), "2.5");
+    assert_eq!(run("median(5, 1, 3)"), "3");
+    assert_eq!(run("median(4, 1, 3, 2)"), "2.5");
+    assert_eq!(run("min(3, -1, 2)"), "-1");
+    assert_eq!(run("max(3, -1, 2)"), "3");
+    assert_eq!(run("var(2, 4, 4, 4, 5, 5, 7, 9)"), "4.571428571428571");
+    assert_eq!(run("stddev(1, 3)"), "1.4142135623730951");
+    assert_eq!(run("sum(7)"), "7");
+    assert_eq!(run("max(1, 2) + min(sum(1, 1), 3)"), "4");
+    match tokenize("sum()").and_then(parse) {
+        Err(ParseError { kind: ErrorKind::WrongArgumentCount(ref name, 1, 0), .. }) if name == "sum" => (),
+        other => panic!("expected wrong argument count, got {:?}", other),
+    }
+    for input in &["var(5)", "stddev(5)"] {
+        match tokenize(input).and_then(parse) {
+            Err(ParseError { kind: ErrorKind::WrongArgumentCount(_, 2, 1), .. }) => (),
+            other => panic!("expected {} to need two values, got {:?}", input, other),
+        }
+    }
+
+    let exact = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut Environment::exact())).unwrap();
+    assert_eq!(exact("sum(1/3, 1/3)"), "2/3");
+    assert_eq!(exact("mean(1/3, 1/2, 1)"), "11/18");
+    assert_eq!(exact("median(1/3, 1/4, 1/2, 1)"), "5/12");
+    assert_eq!(exact("min(1/3, 1/4, 1/2) + max(1/3, 1/4, 1/2)"), "0.75");
+    assert_eq!(exact("var(1/3, 2/3, 1)"), "1/9");
+    assert_eq!(exact("log(8, sqrt(4))"), "3");
+    assert_eq!(exact("sum(1/3, sqrt(4))"), (1.0f64 / 3.0 + 2.0).to_string());
+}
+
+#[test]
+fn complex_numbers() {
+    let run = |input: &str| tokenize(input).and_then(parse).unwrap();
+    assert_eq!(run("sqrt(-1)"), "i");
+    assert_eq!(run("sqrt(-4)"), "2i");
+    assert_eq!(run("-sqrt(-2.25)"), "-1.5i");
+    assert_eq!(run("i^2"), "-1");
+    assert_eq!(run("(1 + 2*i) * (3 - i)"), "5+5i");
+    assert_eq!(run("(1 + i) / (1 - i)"), "i");
+    assert_eq!(run("2 - 3*i"), "2-3i");
+    assert_eq!(run("(1 + i)^-2"), "-0.5i");
+    assert_eq!(run("abs(3 + 4*i)"), "5");
+    assert_eq!(run("re(3 - 4*i) + im(3 - 4*i)"), "-1");
+    assert_eq!(run("conj(3 - 4*i)"), "3+4i");
+    assert_eq!(run("arg(i) == pi/2"), "true");
+    assert_eq!(run("arg(-1) == pi"), "true");
+    assert_eq!(run("ln(-1)"), "3.141592653589793i");
+    assert_eq!(run("abs(re((-8)^(1/3)) - 1) < 0.000000000001"), "true");
+    assert_eq!(run("abs(im((-8)^(1/3)) - sqrt(3)) < 0.000000000001"), "true");
+    assert_eq!(run("i == sqrt(-1)"), "true");
+    assert_eq!(run("sqrt(2.25) + abs(-2)"), "3.5");
+}
+
+#[test]
+fn complex_errors() {
+    assert!(tokenize("i < 1").and_then(parse).is_err());
+    assert!(tokenize("sin(i)").and_then(parse).is_err());
+    assert!(tokenize("i & 1").and_then(parse).is_err());
+    assert!(tokenize("1 / (0*i)").and_then(parse).is_err());
+}
+
+#[test]
+fn error_spans() {
+    let span = |input: &str| tokenize(input).and_then(parse).unwrap_err().span;
+    assert_eq!(span("1 + * 2"), Span::new(4, 5));
+    assert_eq!(span("(1 + 2) )"), Span::new(8, 9));
+    assert_eq!(span("2 *"), Span::new(3, 3));
+    assert_eq!(span("1 + 2/0"), Span::new(4, 7));
+    assert_eq!(span("sqrt(1, 2) + 1"), Span::new(0, 10));
+    assert_eq!(span("é + $ 2"), Span::new(5, 6));
+    assert_eq!(span("(1 + 2"), Span::new(6, 6));
+}
+
+#[test]
+fn implicit_multiplication() {
+    let run = |input: &str| tokenize(input).and_then(parse).unwrap();
+    assert_eq!(run("2(3+4)"), "14");
+    assert_eq!(run("(1 + 2)(3 + 4)"), "21");
+    assert_eq!(run("3pi"), run("3 * pi"));
+    assert_eq!(run("2 sqrt(16)"), "8");
+    assert_eq!(run("2i * 2i"), "-4");
+    assert_eq!(run("1/2(4)"), "2");
+    assert_eq!(run("2e"), run("2 * e"));
+
+    // Numbers side by side are not multiplied, and there is no scientific notation
+    let kind = |input: &str| tokenize(input).and_then(parse).unwrap_err().kind;
+    match kind("2 3") {
+        ErrorKind::UnexpectedToken(ref found, "operator") if found == "Number" => (),
+        other => panic!("expected unexpected token, got {:?}", other),
+    }
+    match kind("1..2") {
+        ErrorKind::UnexpectedToken(ref found, "operator") if found == "Number" => (),
+        other => panic!("expected unexpected token, got {:?}", other),
+    }
+    assert!(tokenize("1.(2)").and_then(parse).is_err());
+    assert!(tokenize("2. pi").and_then(parse).is_err());
+    match kind("1e-3") {
+        ErrorKind::InvalidNumber(ref literal) if literal == "1e-3" => (),
+        other => panic!("expected invalid number, got {:?}", other),
+    }
+    match kind("2 * 6.02E23") {
+        ErrorKind::InvalidNumber(ref literal) if literal == "6.02E23" => (),
+        other => panic!("expected invalid number, got {:?}", other),
+    }
+    assert_eq!(tokenize("1e-3").unwrap_err().span, Span::new(0, 4));
+}
+
+#[test]
+fn percentages() {
+    let run = |input: &str| tokenize(input).and_then(parse).unwrap();
+    assert_eq!(run("200 + 15%"), "230");
+    assert_eq!(run("200 - 15%"), "170");
+    assert_eq!(run("200 * 15%"), "30");
+    assert_eq!(run("50%"), "0.5");
+    assert_eq!(run("-50%"), "-0.5");
+    assert_eq!(run("(100 + 10%) + 10%"), "121");
+    assert_eq!(run("17 % 5"), "2");
+    assert_eq!(run("17 % (2 + 3)"), "2");
+    assert_eq!(run("(10%) - 1"), "-0.9");
+    assert_eq!(run("1GB + 5% in MB"), "1050 MB");
+    assert_eq!(run("max(50%, 1%)"), "0.5");
+    assert_eq!(run("true ? 5% : 1"), "0.05");
+    assert_eq!(run("50% * 2"), "1");
+
+    // Written against its operand, `%` stays a percentage before a plus or minus
+    assert_eq!(run("10% - 1"), "-0.9");
+    assert_eq!(run("10%-1"), "-0.9");
+    assert_eq!(run("-50% + 1"), "0.5");
+    assert_eq!(run("200 + 15% + 5"), "235");
+    assert_eq!(run("200 + 15% * 2"), "260");
+    assert_eq!(run("200 - 2 * 15%"), "140");
+    assert_eq!(run("200 + -15%"), "170");
+
+    // Followed by something which can start an operand, `%` is a modulo
+    assert_eq!(run("3 % -1"), "0");
+    assert_eq!(run("7 % -4"), "-1");
+    assert_eq!(run("7 %+4"), "3");
+    assert_eq!(run("5 % ~1"), "-1");
+    for input in &["7 % - 4", "10 % + 1"] {
+        match tokenize(input).and_then(parse) {
+            Err(ParseError { kind: ErrorKind::OtherError(ref message), .. }) if message.starts_with("Ambiguous %") => (),
+            other => panic!("expected {} to be ambiguous, got {:?}", input, other),
+        }
+    }
+    let exact = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut Environment::exact())).unwrap();
+    assert_eq!(exact("1/3 + 10%"), "11/30");
+
+    let tokens = tokenize("2^3% + -4%").unwrap();
+    match Parser::new(&tokens).parse_statement().unwrap() {
+        Statement::Expression(ref expr) => {
+            assert_eq!(render(expr), "(Add (Percent (Exponent 2 3)) (Negate (Percent 4)))");
+            assert_eq!(expr.to_string(), "2^3% + -4%");
+        }
+        other => panic!("expected an expression, got {:?}", other),
+    }
+}
+
+#[test]
+fn dates() {
+    let run = |input: &str| tokenize(input).and_then(parse).unwrap();
+    assert_eq!(run("2026-10-17 + 90d"), "2027-01-15");
+    assert_eq!(run("2026-10-17 - 2026-01-01"), "289d");
+    assert_eq!(run("2024-03-01 - 2024-02-01 in d"), "29 d");
+    assert_eq!(run("2026-10-17T09:30 + 45min"), "2026-10-17T10:15:00Z");
+    assert_eq!(run("2026-12-31T23:00 + 2h"), "2027-01-01T01:00:00Z");
+    assert_eq!(run("1969-12-31T12:00 + 1wk"), "1970-01-07T12:00:00Z");
+    assert_eq!(run("17:45 - 09:10"), "8h 35min");
+    assert_eq!(run("17:45:30 - 17:45"), "30s");
+    assert_eq!(run("(17:45 - 09:10) in min"), "515 min");
+    assert_eq!(run("2026-10-17 + 17:45"), "2026-10-17T17:45:00Z");
+    assert_eq!(run("90000s in human"), "1d 1h");
+    assert_eq!(run("2026-10-17 > 2026-01-01"), "true");
+    assert_eq!(run("2026-10-17 == 2026-10-16 + 1d"), "true");
+    assert_eq!(run("true ? 1 : 30"), "1");
+    // Within a conditional, a colon separates the branches unless it is inside parentheses
+    assert_eq!(run("true ? 10:20"), "10");
+    assert_eq!(run("false ? 10:20"), "20");
+    assert_eq!(run("true ? (10:20) : 3"), "10h 20min");
+    assert_eq!(run("false ? 1 : 10:20 - 9:50"), "30min");
+    assert_eq!(run("(true ? 2 : 3) * 1h + 1:30 in min"), "210 min");
+    assert_eq!(run("9999-12-31T23:00 + 30min"), "9999-12-31T23:30:00Z");
+    assert_eq!(run("now - now < 1s"), "true");
+    assert_eq!(run("today <= now"), "true");
+    let exact = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut Environment::exact())).unwrap();
+    assert_eq!(exact("2026-10-17 + 1.5d"), "2026-10-18T12:00:00Z");
+
+    let mut env = Environment::new();
+    env.format.offset = -5 * 3600;
+    let mut run = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut env)).unwrap();
+    assert_eq!(run("2026-10-17T09:30 + 1h"), "2026-10-17T10:30:00-05:00");
+    assert_eq!(run("2026-10-17 + 1d"), "2026-10-18");
+    assert_eq!(date::format_date(86400.0, std::i64::MAX), "86400");
+    assert_eq!(date::format_date(1e100, 3600), "1e100".parse::<f64>().unwrap().to_string());
+
+    let mut env = Environment::new();
+    env.format.offset = 14 * 3600;
+    match tokenize("0000-01-01").and_then(|t| parse_with_env(t, &mut env)) {
+        Err(ParseError { kind: ErrorKind::InvalidNumber(_), .. }) => (),
+        other => panic!("expected invalid number, got {:?}", other),
+    }
+}
+
+#[test]
+fn date_errors() {
+    let kind = |input: &str| tokenize(input).and_then(parse).unwrap_err().kind;
+    match kind("2026-02-30 + 1d") {
+        ErrorKind::InvalidNumber(ref literal) if literal == "2026-02-30" => (),
+        other => panic!("expected invalid number, got {:?}", other),
+    }
+    match kind("25:00 - 1:00") {
+        ErrorKind::InvalidNumber(ref literal) if literal == "25:00" => (),
+        other => panic!("expected invalid number, got {:?}", other),
+    }
+    match kind("2026-10-17 + 2026-10-17") {
+        ErrorKind::TypeMismatch("duration", "date") => (),
+        other => panic!("expected type mismatch, got {:?}", other),
+    }
+    match kind("2026-10-17 + 5") {
+        ErrorKind::IncompatibleUnits(ref a, ref b) if a == "s" && b == "no unit" => (),
+        other => panic!("expected incompatible units, got {:?}", other),
+    }
+    match kind("2026-10-17 * 2") {
+        ErrorKind::TypeMismatch("number", "date") => (),
+        other => panic!("expected type mismatch, got {:?}", other),
+    }
+    match kind("1d - 2026-10-17") {
+        ErrorKind::TypeMismatch("date", "number") => (),
+        other => panic!("expected type mismatch, got {:?}", other),
+    }
+    for input in &["9999-12-31 + 1d", "1970-01-01 - 10^100 * 1s", "now + 2^70 * 1s"] {
+        match kind(input) {
+            ErrorKind::OtherError(ref message) if message == "Date out of range" => (),
+            other => panic!("expected {} to be out of range, got {:?}", input, other),
+        }
+    }
+    assert!(tokenize("true ? 10:205 : 3").and_then(parse).is_err());
+    // With the colon read as the conditional's, the `: 3` which follows has nothing to belong to
+    assert!(tokenize("true ? 10:20 : 3").and_then(parse).is_err());
+    assert!(tokenize("sqrt(now)").and_then(parse).is_err());
+    assert!(tokenize("2026-10-17 in d").and_then(parse).is_err());
+    assert_eq!(parse_offset("+05:30"), Some(19800));
+    assert_eq!(parse_offset("-0800"), Some(-28800));
+    assert_eq!(parse_offset("Z"), Some(0));
+    assert_eq!(parse_offset("+5"), None);
+}
+
+#[test]
+fn units() {
+    assert_eq!(tokenize("3GiB / 4KiB").and_then(parse).unwrap(), "786432");
+    assert_eq!(tokenize("90min in s").and_then(parse).unwrap(), "5400 s");
+    assert_eq!(tokenize("1h + 30min").and_then(parse).unwrap(), "1.5 h");
+    assert_eq!(tokenize("1h + 30min to min").and_then(parse).unwrap(), "90 min");
+    assert_eq!(tokenize("2 * 512MB").and_then(parse).unwrap(), "1024 MB");
+    assert_eq!(tokenize("1GB in MiB").and_then(parse).unwrap(), "953.67431640625 MiB");
+    assert_eq!(tokenize("8bit in B").and_then(parse).unwrap(), "1 B");
+    assert_eq!(tokenize("1KiB / 2s").and_then(parse).unwrap(), "512 B/s");
+    assert_eq!(tokenize("(3m)^2").and_then(parse).unwrap(), "9 m^2");
+    assert_eq!(tokenize("1.5km in m").and_then(parse).unwrap(), "1500 m");
+    assert_eq!(tokenize("250ms * 4 in s").and_then(parse).unwrap(), "1 s");
+    assert_eq!(tokenize("5 min").and_then(parse).unwrap(), "5 min");
+    assert_eq!(tokenize("3 KiB").and_then(parse).unwrap(), "3 KiB");
+    assert_eq!(tokenize("3 KiB in B").and_then(parse).unwrap(), "3072 B");
+    assert_eq!(tokenize("1h + 30 min to min").and_then(parse).unwrap(), "90 min");
+    assert_eq!(tokenize("5 min(2, 3)").and_then(parse).unwrap(), "10");
+    let exact = |input: &str| tokenize(input).and_then(|t| parse_with_env(t, &mut Environment::exact()));
+    assert_eq!(exact("100ms in min").unwrap(), "1/600 min");
+
+    // A variable or parameter named like a unit is not a unit
+    let mut evaluator = Evaluator::new();
+    assert_eq!(evaluator.run("m = 3").unwrap(), "3");
+    assert_eq!(evaluator.run("2 m").unwrap(), "6");
+    assert_eq!(evaluator.run("2 s").unwrap(), "2 s");
+    assert_eq!(evaluator.run("f(s) = 4 s").unwrap(), "f(s) = 4s");
+    assert_eq!(evaluator.run("f(5)").unwrap(), "20");
+}
+
+#[test]
+fn unit_errors() {
+    let kind = |input: &str| tokenize(input).and_then(parse).unwrap_err().kind;
+    match kind("1s + 1B") {
+        ErrorKind::IncompatibleUnits(ref a, ref b) if a == "s" && b == "B" => (),
+        other => panic!("expected incompatible units, got {:?}", other),
+    }
+    match kind("1h in KiB") {
+        ErrorKind::IncompatibleUnits(ref a, ref b) if a == "s" && b == "B" => (),
+        other => panic!("expected incompatible units, got {:?}", other),
+    }
+    match kind("1h in parsec") {
+        ErrorKind::UnknownUnit(ref unit) if unit == "parsec" => (),
+        other => panic!("expected unknown unit, got {:?}", other),
+    }
+    assert!(tokenize("1 + 1s").and_then(parse).is_err());
+    assert!(tokenize("sqrt(4s)").and_then(parse).is_err());
+    assert!(tokenize("3KiB & 1").and_then(parse).is_err());
+}
+
+#[test]
+fn invalid_assignment() {
+    assert!(tokenize("1 = 2").and_then(parse).is_err());
+    assert!(tokenize("x = = 2").and_then(parse).is_err());
+}
+
+#[test]
+fn evaluator() {
+    let mut evaluator = Evaluator::new();
+    assert_eq!(evaluator.evaluate("x = 6 * 7").unwrap().to_f64(), 42.0);
+    assert_eq!(evaluator.evaluate("x + 1").unwrap().to_f64(), 43.0);
+    assert_eq!(evaluator.evaluate("ans > x").unwrap(), Value::Bool(true));
+    assert_eq!(evaluator.run("sq(n) = n^2").unwrap(), "sq(n) = n^2");
+    assert_eq!(evaluator.run("sq(x)").unwrap(), "1764");
+
+    let e = evaluator.evaluate("cube(n) = n^3").unwrap_err();
+    assert_eq!(e.to_string(), "Error: Function definitions have no value");
+    assert!(evaluator.environment().function("cube").is_none());
+    let e = evaluator.evaluate("2 + y").unwrap_err();
+    assert_eq!(e.to_string(), "Error: Unknown variable: y");
+    assert_eq!((e.span.start, e.span.end), (4, 5));
+    for &(input, message) in &[("1/0", "Error: Divide by zero error"),
+                               ("1 << -1", "Error: Negative shift amount"),
+                               ("f(a, a) = a", "Error: Duplicate parameter: a"),
+                               ("sqrt(n) = n", "Error: Cannot redefine built-in function sqrt")] {
+        assert_eq!(evaluator.run(input).unwrap_err().to_string(), message);
+    }
+
+    let mut exact = Evaluator::exact();
+    let third = exact.evaluate("1/3").unwrap();
+    assert_eq!(exact.format(&third), "1/3");
+}
@@ -0,0 +1,343 @@
+//! Splitting a line of input into tokens.
+
+use std::cmp;
+
+use super::error::{ErrorKind, ParseError};
+use super::unit::find_unit;
+
+#[derive(Debug, Clone)]
+pub enum Token {
+    Plus,
+    Minus,
+    Divide,
+    Multiply,
+    Exponent,
+    Modulo,
+    IntDivide,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+    OpenParen,
+    CloseParen,
+    Assign,
+    Comma,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    Not,
+    Question,
+    Colon,
+    In,
+    Number(String),
+    Unit(String),
+    Identifier(String),
+}
+
+impl Token {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            &Token::Plus          => "Plus",
+            &Token::Minus         => "Minus",
+            &Token::Divide        => "Divide",
+            &Token::Multiply      => "Multiply",
+            &Token::Exponent      => "Exponent",
+            &Token::Modulo        => "Modulo",
+            &Token::IntDivide     => "IntDivide",
+            &Token::BitAnd        => "BitAnd",
+            &Token::BitOr         => "BitOr",
+            &Token::BitXor        => "BitXor",
+            &Token::BitNot        => "BitNot",
+            &Token::ShiftLeft     => "ShiftLeft",
+            &Token::ShiftRight    => "ShiftRight",
+            &Token::OpenParen     => "OpenParen",
+            &Token::CloseParen    => "CloseParen",
+            &Token::Assign        => "Assign",
+            &Token::Comma         => "Comma",
+            &Token::Equal         => "Equal",
+            &Token::NotEqual      => "NotEqual",
+            &Token::Less          => "Less",
+            &Token::LessEqual     => "LessEqual",
+            &Token::Greater       => "Greater",
+            &Token::GreaterEqual  => "GreaterEqual",
+            &Token::And           => "And",
+            &Token::Or            => "Or",
+            &Token::Not           => "Not",
+            &Token::Question      => "Question",
+            &Token::Colon         => "Colon",
+            &Token::In            => "In",
+            &Token::Number(_)     => "Number",
+            &Token::Unit(_)       => "Unit",
+            &Token::Identifier(_) => "Identifier",
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        self.to_str().to_owned()
+    }
+}
+
+/// A range of byte offsets into the input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span {
+            start: start,
+            end: end,
+        }
+    }
+
+    /// The smallest span covering both spans.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(cmp::min(self.start, other.start), cmp::max(self.end, other.end))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+pub trait OperatorFunctions {
+    fn is_operator(self) -> bool;
+    fn operator_type(self) -> Option<Token>;
+}
+
+impl OperatorFunctions for char {
+    fn is_operator(self) -> bool {
+        self.operator_type().is_some()
+    }
+
+    /// The token for a single character operator, or `None` if the character is not one.
+    fn operator_type(self) -> Option<Token> {
+        match self {
+            '+' => Some(Token::Plus),
+            '-' => Some(Token::Minus),
+            '/' => Some(Token::Divide),
+            '*' => Some(Token::Multiply),
+            '^' => Some(Token::Exponent),
+            '(' => Some(Token::OpenParen),
+            ')' => Some(Token::CloseParen),
+            '=' => Some(Token::Assign),
+            ',' => Some(Token::Comma),
+            '%' => Some(Token::Modulo),
+            '&' => Some(Token::BitAnd),
+            '|' => Some(Token::BitOr),
+            '~' => Some(Token::BitNot),
+            '<' => Some(Token::Less),
+            '>' => Some(Token::Greater),
+            '!' => Some(Token::Not),
+            '?' => Some(Token::Question),
+            ':' => Some(Token::Colon),
+            _   => None,
+        }
+    }
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<SpannedToken>, ParseError> {
+    let mut tokens = Vec::with_capacity(input.len());
+
+    // TODO: Not this. Modify to use iterator
+    let chars: Vec<char> = input.chars().collect();
+    // The byte offset of each character, followed by the length of the input
+    let offsets: Vec<usize> = input.char_indices().map(|(i, _)| i).chain(Some(input.len())).collect();
+
+    // The number of `?` still waiting for their `:` within each open parenthesis. While there are
+    // any, `10:20` is read as the two branches of the conditional rather than as a time of day.
+    let mut conditionals = vec![0usize];
+
+    let input_length = chars.len();
+    let mut current_pos = 0;
+    while current_pos < input_length {
+        let c = chars[current_pos];
+        let (token, length) = if c.is_digit(10) || c == '.' {
+            let clocks = conditionals.last() == Some(&0);
+            let token_string = consume_number(&chars[current_pos..], clocks);
+            let length = token_string.chars().count();
+            // There is no scientific notation, and `1e-3` must not quietly mean `1*e - 3`
+            if let Some(exponent) = exponent_length(&chars[current_pos + length..]) {
+                let end = current_pos + length + exponent;
+                let literal = chars[current_pos..end].iter().cloned().collect();
+                return Err(ParseError::new(ErrorKind::InvalidNumber(literal), Span::new(offsets[current_pos], offsets[end])));
+            }
+            (Token::Number(token_string), length)
+        } else if c.is_alphabetic() || c == '_' {
+            let token_string = consume_identifier(&chars[current_pos..]);
+            let length = token_string.chars().count();
+            // A unit after a number, as in 4KiB or 5 min, unless it is called like a function
+            let after_number = match tokens.last() {
+                Some(&SpannedToken { token: Token::Number(_), .. }) => {
+                    chars[current_pos + length..].iter().find(|c| !c.is_whitespace()) != Some(&'(')
+                }
+                _ => false,
+            };
+            match token_string.as_str() {
+                "xor" => (Token::BitXor, length),
+                "in" | "to" => (Token::In, length),
+                name if after_number && find_unit(name).is_some() => (Token::Unit(token_string.clone()), length),
+                _ => (Token::Identifier(token_string), length),
+            }
+        } else if let Some(token) = two_char_operator(&chars[current_pos..]) {
+            (token, 2)
+        } else if let Some(token) = c.operator_type() {
+            (token, 1)
+        } else if c.is_whitespace() {
+            current_pos += 1;
+            continue;
+        } else {
+            let token_string = consume_until_new_token(&chars[current_pos..]);
+            let span = Span::new(offsets[current_pos], offsets[current_pos + token_string.chars().count()]);
+            return Err(ParseError::new(ErrorKind::UnrecognizedToken(token_string), span));
+        };
+
+        match token {
+            Token::OpenParen => conditionals.push(0),
+            Token::CloseParen if conditionals.len() > 1 => {
+                conditionals.pop();
+            }
+            Token::Question => *conditionals.last_mut().unwrap() += 1,
+            Token::Colon => {
+                let pending = conditionals.last_mut().unwrap();
+                *pending = pending.saturating_sub(1);
+            }
+            _ => (),
+        }
+        tokens.push(SpannedToken {
+            token: token,
+            span: Span::new(offsets[current_pos], offsets[current_pos + length]),
+        });
+        current_pos += length;
+    }
+    Ok(tokens)
+}
+
+fn two_char_operator(input: &[char]) -> Option<Token> {
+    match (input.get(0), input.get(1)) {
+        (Some(&'/'), Some(&'/')) => Some(Token::IntDivide),
+        (Some(&'<'), Some(&'<')) => Some(Token::ShiftLeft),
+        (Some(&'>'), Some(&'>')) => Some(Token::ShiftRight),
+        (Some(&'='), Some(&'=')) => Some(Token::Equal),
+        (Some(&'!'), Some(&'=')) => Some(Token::NotEqual),
+        (Some(&'<'), Some(&'=')) => Some(Token::LessEqual),
+        (Some(&'>'), Some(&'=')) => Some(Token::GreaterEqual),
+        (Some(&'&'), Some(&'&')) => Some(Token::And),
+        (Some(&'|'), Some(&'|')) => Some(Token::Or),
+        _ => None,
+    }
+}
+
+// Read a number, a date, or a time of day if `clocks` allows it
+fn consume_number(input: &[char], clocks: bool) -> String {
+    // A radix prefix takes every alphanumeric character that follows, the digits are checked later
+    if input.len() > 2 && input[0] == '0' && "xXoObB".contains(input[1]) && input[2].is_alphanumeric() {
+        return input.iter()
+                    .take_while(|&&c| c.is_alphanumeric() || c == '_')
+                    .map(|&c| c)
+                    .collect();
+    }
+    if let Some(length) = date_length(input).or_else(|| if clocks { clock_length(input) } else { None }) {
+        return input[..length].iter().cloned().collect();
+    }
+
+    let mut number = String::with_capacity(input.len());
+    let mut has_decimal_point = false;
+    for (i, &c) in input.iter().enumerate() {
+        if c == '.' {
+            if has_decimal_point {
+                break;
+            } else {
+                number.push(c);
+                has_decimal_point = true;
+            }
+        } else if c.is_digit(10) {
+            number.push(c);
+        } else if c == '_' && i > 0 && input[i - 1].is_digit(10) &&
+                  input.get(i + 1).map_or(false, |c| c.is_digit(10)) {
+            // Digit separator, as in 1_000_000
+            number.push(c);
+        } else {
+            break;
+        }
+    }
+    number
+}
+
+// The length of an exponent such as `e-3` or `E10` at the start of the input
+fn exponent_length(input: &[char]) -> Option<usize> {
+    match input.get(0) {
+        Some(&'e') | Some(&'E') => (),
+        _ => return None,
+    }
+    let sign = match input.get(1) {
+        Some(&'+') | Some(&'-') => 1,
+        _ => 0,
+    };
+    digits(&input[1 + sign..], 1, usize::max_value()).map(|length| 1 + sign + length)
+}
+
+// The length of a run of between `min` and `max` digits at the start of the input
+fn digits(input: &[char], min: usize, max: usize) -> Option<usize> {
+    let length = input.iter().take_while(|c| c.is_digit(10)).count();
+    if length >= min && length <= max { Some(length) } else { None }
+}
+
+// The length of a separator followed by two digits at the start of the input
+fn field(input: &[char], separator: char) -> Option<usize> {
+    match input.get(0) {
+        Some(&c) if c == separator => digits(&input[1..], 2, 2).map(|length| length + 1),
+        _ => None,
+    }
+}
+
+// The length of a time of day such as `9:30` or `17:45:30` at the start of the input
+fn clock_length(input: &[char]) -> Option<usize> {
+    digits(input, 1, 2).and_then(|hours| field(&input[hours..], ':').map(|minutes| hours + minutes))
+                       .map(|length| length + field(&input[length..], ':').unwrap_or(0))
+}
+
+// The length of a date such as `2026-10-17` or `2026-10-17T09:30` at the start of the input
+fn date_length(input: &[char]) -> Option<usize> {
+    let mut length = match digits(input, 4, 4) {
+        Some(year) => year,
+        None => return None,
+    };
+    for _ in 0..2 {
+        length += match field(&input[length..], '-') {
+            Some(field) => field,
+            None => return None,
+        };
+    }
+    if input.get(length) == Some(&'T') {
+        if let Some(clock) = clock_length(&input[length + 1..]) {
+            length += clock + 1;
+        }
+    }
+    Some(length)
+}
+
+fn consume_identifier(input: &[char]) -> String {
+    input.iter()
+         .take_while(|&&c| c.is_alphanumeric() || c == '_')
+         .map(|&c| c)
+         .collect()
+}
+
+fn consume_until_new_token(input: &[char]) -> String {
+    input.iter()
+         .take_while(|c| !(c.is_whitespace() || c.is_operator() || c.is_digit(10)))
+         .map(|&c| c)
+         .collect()
+}
@@ -0,0 +1,166 @@
+//! Units of measurement and their prefixes.
+
+use super::number::{BigInt, Rational};
+
+// The base units every quantity is stored in: bytes, seconds, metres and grams
+const BASE_UNITS: [&'static str; 4] = ["B", "s", "m", "g"];
+
+/// The exponent of each base unit in a quantity, so that bytes per second is `[1, -1, 0, 0]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dimension([i8; 4]);
+
+impl Dimension {
+    pub fn is_none(&self) -> bool {
+        self.0.iter().all(|&e| e == 0)
+    }
+
+    // The dimension of a product of quantities, or of a quotient when `sign` is -1
+    pub fn combine(&self, other: &Dimension, sign: i8) -> Dimension {
+        let mut result = self.0;
+        for i in 0..result.len() {
+            result[i] += sign * other.0[i];
+        }
+        Dimension(result)
+    }
+
+    pub fn scale(&self, n: i8) -> Dimension {
+        let mut result = self.0;
+        for e in result.iter_mut() {
+            *e *= n;
+        }
+        Dimension(result)
+    }
+
+    /// The dimension written in base units, such as `B/s` or `m^2`.
+    pub fn name(&self) -> String {
+        let part = |i: usize, e: i8| if e == 1 { BASE_UNITS[i].to_owned() } else { format!("{}^{}", BASE_UNITS[i], e) };
+        let numerator: Vec<String> = (0..4).filter(|&i| self.0[i] > 0).map(|i| part(i, self.0[i])).collect();
+        let denominator: Vec<String> = (0..4).filter(|&i| self.0[i] < 0).map(|i| part(i, -self.0[i])).collect();
+        match (numerator.is_empty(), denominator.is_empty()) {
+            (true, true) => "no unit".to_owned(),
+            (false, true) => numerator.join("*"),
+            (true, false) => ["1/", &denominator.join("/")].concat(),
+            (false, false) => [numerator.join("*"), denominator.join("/")].join("/"),
+        }
+    }
+}
+
+/// A unit of measurement: its name and its size in base units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unit {
+    pub name: String,
+    pub factor: Rational,
+    pub dimension: Dimension,
+}
+
+impl Unit {
+    // The unit a product or quotient of quantities is shown in, or `None` if the units cancel out
+    pub fn combine(a: Option<&Unit>, b: Option<&Unit>, sign: i8) -> Option<Unit> {
+        let dimension = match (a, b) {
+            (Some(a), None) => return Some(a.clone()),
+            (None, Some(b)) if sign == 1 => return Some(b.clone()),
+            (None, Some(b)) => Dimension([0; 4]).combine(&b.dimension, sign),
+            (Some(a), Some(b)) => a.dimension.combine(&b.dimension, sign),
+            (None, None) => return None,
+        };
+        Unit::base(dimension)
+    }
+
+    /// The unit of a dimension written in base units, or `None` for plain numbers.
+    pub fn base(dimension: Dimension) -> Option<Unit> {
+        if dimension.is_none() {
+            None
+        } else {
+            Some(Unit {
+                name: dimension.name(),
+                factor: Rational::from_integer(BigInt::from_u64(1)),
+                dimension: dimension,
+            })
+        }
+    }
+}
+
+// Which prefixes a unit accepts
+#[derive(Clone, Copy, PartialEq)]
+enum Prefixes {
+    None,
+    // Multiples only, including the binary prefixes
+    Information,
+    // Fractions only
+    Time,
+    All,
+}
+
+// Name, size in base units as a fraction, base unit index and accepted prefixes
+static UNITS: &'static [(&'static str, u64, u64, usize, Prefixes)] = &[
+    ("B",   1,      1, 0, Prefixes::Information),
+    ("bit", 1,      8, 0, Prefixes::Information),
+    ("s",   1,      1, 1, Prefixes::Time),
+    ("min", 60,     1, 1, Prefixes::None),
+    ("h",   3600,   1, 1, Prefixes::None),
+    ("d",   86400,  1, 1, Prefixes::None),
+    ("wk",  604800, 1, 1, Prefixes::None),
+    ("human", 1,    1, 1, Prefixes::None),
+    ("m",   1,      1, 2, Prefixes::All),
+    ("g",   1,      1, 3, Prefixes::All),
+];
+
+// SI prefixes and their powers of ten
+static SI_PREFIXES: &'static [(&'static str, i32)] = &[
+    ("n", -9), ("u", -6), ("µ", -6), ("m", -3), ("c", -2),
+    ("k", 3), ("K", 3), ("M", 6), ("G", 9), ("T", 12), ("P", 15),
+];
+
+// Binary prefixes and their powers of two
+static BINARY_PREFIXES: &'static [(&'static str, u64)] = &[
+    ("Ki", 10), ("Mi", 20), ("Gi", 30), ("Ti", 40), ("Pi", 50),
+];
+
+/// The unit durations between dates are shown in, which writes them as days, hours, minutes and
+/// seconds. Other durations can be converted to it with `in human`.
+pub fn duration_unit() -> Unit {
+    find_unit("human").unwrap()
+}
+
+/// Look up a unit such as `KiB`, `ms` or `h` by name.
+pub fn find_unit(name: &str) -> Option<Unit> {
+    for &(unit, numerator, denominator, base, prefixes) in UNITS {
+        let mut dimension = [0; 4];
+        dimension[base] = 1;
+        let factor = Rational::new(BigInt::from_u64(numerator), BigInt::from_u64(denominator));
+        let make = |scale: Rational| Unit {
+            name: name.to_owned(),
+            factor: factor.mul(&scale),
+            dimension: Dimension(dimension),
+        };
+
+        if name == unit {
+            return Some(make(Rational::from_integer(BigInt::from_u64(1))));
+        }
+        if prefixes == Prefixes::None || !name.ends_with(unit) {
+            continue;
+        }
+
+        let prefix = &name[..name.len() - unit.len()];
+        for &(p, power) in SI_PREFIXES {
+            let allowed = match prefixes {
+                Prefixes::Information => power > 0,
+                Prefixes::Time => power < 0,
+                _ => p != "K",
+            };
+            if p == prefix && allowed {
+                let ten = BigInt::from_u64(10).pow(power.abs() as u64);
+                let one = BigInt::from_u64(1);
+                return Some(make(if power > 0 { Rational::new(ten, one) } else { Rational::new(one, ten) }));
+            }
+        }
+        if prefixes == Prefixes::Information {
+            for &(p, power) in BINARY_PREFIXES {
+                if p == prefix {
+                    return Some(make(Rational::from_integer(BigInt::from_u64(2).pow(power))));
+                }
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,439 @@
+//! The values expressions evaluate to.
+
+use std::cmp::{self, Ordering};
+use std::fmt;
+
+use super::error::ErrorKind;
+use super::date::{self, format_date, format_duration};
+use super::number::{BigInt, Complex, Rational};
+use super::unit::{duration_unit, Unit};
+
+// The most bits the numerator or denominator of an exact power may have, as many as the largest
+// shift gives, so that `2^(2^40)` fails rather than running out of memory
+const MAX_POWER_BITS: u64 = 65536;
+
+/// The result of evaluating an expression. Exact values are produced in exact mode and stay exact
+/// until they meet a floating point operand or function. A quantity is a number of base units,
+/// along with the unit it should be shown in. Complex values always have a nonzero imaginary
+/// part; results which come out real are plain floats. Booleans come from comparisons and logical
+/// operators and cannot be used in arithmetic. A date is a number of seconds since the Unix epoch,
+/// which durations can be added to and taken from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Float(f64),
+    Exact(Rational),
+    Complex(Complex),
+    Quantity(Box<Value>, Unit),
+    Bool(bool),
+    Date(Box<Value>),
+}
+
+impl Value {
+    /// A complex value, or a float if the imaginary part is zero.
+    pub fn from_complex(c: Complex) -> Value {
+        if c.im == 0.0 {
+            Value::Float(c.re)
+        } else {
+            Value::Complex(c)
+        }
+    }
+
+    /// A date this many seconds after the Unix epoch, or an error if it is out of range.
+    pub fn date(seconds: Value) -> Result<Value, ErrorKind> {
+        if date::in_range(seconds.to_f64()) {
+            Ok(Value::Date(Box::new(seconds)))
+        } else {
+            Err(ErrorKind::OtherError("Date out of range".to_owned()))
+        }
+    }
+
+    pub fn to_complex(&self) -> Complex {
+        match *self {
+            Value::Complex(c) => c,
+            _ => Complex::new(self.to_f64(), 0.0),
+        }
+    }
+
+    pub fn is_complex(&self) -> bool {
+        match *self {
+            Value::Complex(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_date(&self) -> bool {
+        match *self {
+            Value::Date(_) => true,
+            _ => false,
+        }
+    }
+
+    /// A quantity, or a plain number if there is no unit.
+    pub fn with_unit(magnitude: Value, unit: Option<Unit>) -> Value {
+        match unit {
+            Some(unit) => Value::Quantity(Box::new(magnitude), unit),
+            None => magnitude,
+        }
+    }
+
+    pub fn unit(&self) -> Option<&Unit> {
+        match *self {
+            Value::Quantity(_, ref unit) => Some(unit),
+            _ => None,
+        }
+    }
+
+    /// The number of base units in a quantity, or the value itself for plain numbers.
+    pub fn magnitude(&self) -> &Value {
+        match *self {
+            Value::Quantity(ref magnitude, _) => magnitude,
+            _ => self,
+        }
+    }
+
+    // The kind of value, for error messages
+    fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Bool(_) => "boolean",
+            Value::Date(_) => "date",
+            _ => "number",
+        }
+    }
+
+    /// Fail unless the value is a number or quantity.
+    pub fn number(&self) -> Result<&Value, ErrorKind> {
+        match *self {
+            Value::Bool(_) | Value::Date(_) => Err(ErrorKind::TypeMismatch("number", self.type_name())),
+            _ => Ok(self),
+        }
+    }
+
+    /// The value of a boolean, failing for numbers.
+    pub fn truth(&self) -> Result<bool, ErrorKind> {
+        match *self {
+            Value::Bool(b) => Ok(b),
+            _ => Err(ErrorKind::TypeMismatch("boolean", self.type_name())),
+        }
+    }
+
+    /// Compare two numbers, or two quantities of the same dimension. `None` means the values are
+    /// unordered, which happens when either is NaN.
+    pub fn compare(&self, other: &Value) -> Result<Option<Ordering>, ErrorKind> {
+        match (self, other) {
+            (&Value::Date(ref a), &Value::Date(ref b)) => return a.compare(b),
+            (&Value::Date(_), _) => return Err(ErrorKind::TypeMismatch("date", other.type_name())),
+            _ => (),
+        }
+        try!(self.number());
+        try!(other.number());
+        if self.magnitude().is_complex() || other.magnitude().is_complex() {
+            return Err(ErrorKind::TypeMismatch("real number", "complex number"));
+        }
+        try!(self.additive_unit(other));
+        match (self.magnitude(), other.magnitude()) {
+            (&Value::Exact(ref a), &Value::Exact(ref b)) => Ok(Some(a.compare(b))),
+            (a, b) => Ok(a.to_f64().partial_cmp(&b.to_f64())),
+        }
+    }
+
+    /// Whether two values are equal. Booleans can only be compared with booleans.
+    pub fn equals(&self, other: &Value) -> Result<bool, ErrorKind> {
+        match (self, other) {
+            (&Value::Bool(a), &Value::Bool(b)) => Ok(a == b),
+            (&Value::Bool(_), _) | (_, &Value::Bool(_)) => {
+                Err(ErrorKind::TypeMismatch(self.type_name(), other.type_name()))
+            }
+            (&Value::Date(_), _) | (_, &Value::Date(_)) => {
+                self.compare(other).map(|ordering| ordering == Some(Ordering::Equal))
+            }
+            _ if self.magnitude().is_complex() || other.magnitude().is_complex() => {
+                try!(self.additive_unit(other));
+                Ok(self.magnitude().to_complex() == other.magnitude().to_complex())
+            }
+            _ => self.compare(other).map(|ordering| ordering == Some(Ordering::Equal)),
+        }
+    }
+
+    // The name of the dimension of the value, for error messages
+    fn dimension_name(&self) -> String {
+        self.unit().map_or("no unit".to_owned(), |unit| unit.dimension.name())
+    }
+
+    /// Fail unless the value is a plain number.
+    pub fn unitless(&self) -> Result<&Value, ErrorKind> {
+        match *self {
+            Value::Quantity(_, ref unit) => Err(ErrorKind::IncompatibleUnits(unit.dimension.name(), "no unit".to_owned())),
+            _ => Ok(self),
+        }
+    }
+
+    /// The same quantity shown in another unit of the same dimension.
+    pub fn convert(&self, unit: &Unit) -> Result<Value, ErrorKind> {
+        match *self {
+            Value::Date(_) => Err(ErrorKind::TypeMismatch("quantity", "date")),
+            Value::Quantity(ref magnitude, ref from) if from.dimension == unit.dimension => {
+                Ok(Value::Quantity(magnitude.clone(), unit.clone()))
+            }
+            _ => Err(ErrorKind::IncompatibleUnits(self.dimension_name(), unit.dimension.name())),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match *self {
+            Value::Float(f) => f,
+            Value::Exact(ref r) => r.to_f64(),
+            Value::Quantity(ref magnitude, _) | Value::Date(ref magnitude) => magnitude.to_f64(),
+            Value::Complex(_) => std::f64::NAN,
+            Value::Bool(b) => if b { 1.0 } else { 0.0 },
+        }
+    }
+
+    pub fn neg(&self) -> Value {
+        match *self {
+            Value::Float(f) => Value::Float(-f),
+            Value::Exact(ref r) => Value::Exact(r.neg()),
+            Value::Complex(c) => Value::Complex(c.neg()),
+            Value::Quantity(ref magnitude, ref unit) => Value::Quantity(Box::new(magnitude.neg()), unit.clone()),
+            Value::Bool(b) => Value::Bool(b),
+            Value::Date(ref seconds) => Value::Date(seconds.clone()),
+        }
+    }
+
+    // Sums and differences need both sides in the same dimension, and keep the left hand unit
+    fn additive_unit(&self, other: &Value) -> Result<Option<Unit>, ErrorKind> {
+        match (self.unit(), other.unit()) {
+            (None, None) => Ok(None),
+            (Some(a), Some(b)) if a.dimension == b.dimension => Ok(Some(a.clone())),
+            _ => Err(ErrorKind::IncompatibleUnits(self.dimension_name(), other.dimension_name())),
+        }
+    }
+
+    // The number of seconds in a duration, which can be added to or taken from a date
+    fn seconds(&self) -> Result<&Value, ErrorKind> {
+        match *self {
+            Value::Quantity(ref magnitude, ref unit) if unit.dimension == duration_unit().dimension => Ok(magnitude),
+            Value::Date(_) => Err(ErrorKind::TypeMismatch("duration", "date")),
+            _ => Err(ErrorKind::IncompatibleUnits(duration_unit().dimension.name(), self.dimension_name())),
+        }
+    }
+
+    pub fn add(&self, other: &Value) -> Result<Value, ErrorKind> {
+        match (self, other) {
+            (&Value::Date(ref date), _) => return date.add(try!(other.seconds())).and_then(Value::date),
+            (_, &Value::Date(ref date)) => return date.add(try!(self.seconds())).and_then(Value::date),
+            _ => (),
+        }
+        if self.unit().is_some() || other.unit().is_some() {
+            let unit = try!(self.additive_unit(other));
+            return self.magnitude().add(other.magnitude()).map(|m| Value::with_unit(m, unit));
+        }
+        if self.is_complex() || other.is_complex() {
+            return Ok(Value::from_complex(self.to_complex().add(other.to_complex())));
+        }
+        match (self, other) {
+            (&Value::Exact(ref a), &Value::Exact(ref b)) => Ok(Value::Exact(a.add(b))),
+            _ => Ok(Value::Float(self.to_f64() + other.to_f64())),
+        }
+    }
+
+    /// The difference of two values. Taking a date from a date gives the duration between them.
+    pub fn sub(&self, other: &Value) -> Result<Value, ErrorKind> {
+        match (self, other) {
+            (&Value::Date(ref a), &Value::Date(ref b)) => {
+                return a.sub(b).map(|s| Value::Quantity(Box::new(s), duration_unit()));
+            }
+            (&Value::Date(ref date), _) => return date.sub(try!(other.seconds())).and_then(Value::date),
+            (_, &Value::Date(_)) => return Err(ErrorKind::TypeMismatch("date", self.type_name())),
+            _ => (),
+        }
+        if self.unit().is_some() || other.unit().is_some() {
+            let unit = try!(self.additive_unit(other));
+            return self.magnitude().sub(other.magnitude()).map(|m| Value::with_unit(m, unit));
+        }
+        if self.is_complex() || other.is_complex() {
+            return Ok(Value::from_complex(self.to_complex().sub(other.to_complex())));
+        }
+        match (self, other) {
+            (&Value::Exact(ref a), &Value::Exact(ref b)) => Ok(Value::Exact(a.sub(b))),
+            _ => Ok(Value::Float(self.to_f64() - other.to_f64())),
+        }
+    }
+
+    pub fn mul(&self, other: &Value) -> Result<Value, ErrorKind> {
+        if self.unit().is_some() || other.unit().is_some() {
+            let unit = Unit::combine(self.unit(), other.unit(), 1);
+            return self.magnitude().mul(other.magnitude()).map(|m| Value::with_unit(m, unit));
+        }
+        if self.is_complex() || other.is_complex() {
+            return Ok(Value::from_complex(self.to_complex().mul(other.to_complex())));
+        }
+        match (self, other) {
+            (&Value::Exact(ref a), &Value::Exact(ref b)) => Ok(Value::Exact(a.mul(b))),
+            _ => Ok(Value::Float(self.to_f64() * other.to_f64())),
+        }
+    }
+
+    pub fn div(&self, other: &Value) -> Result<Value, ErrorKind> {
+        if self.unit().is_some() || other.unit().is_some() {
+            let unit = Unit::combine(self.unit(), other.unit(), -1);
+            return self.magnitude().div(other.magnitude()).map(|m| Value::with_unit(m, unit));
+        }
+        let divide_by_zero = || ErrorKind::OtherError("Divide by zero error".to_owned());
+        if self.is_complex() || other.is_complex() {
+            let divisor = other.to_complex();
+            if divisor.is_zero() {
+                return Err(divide_by_zero());
+            }
+            return Ok(Value::from_complex(self.to_complex().div(divisor)));
+        }
+        match (self, other) {
+            (&Value::Exact(ref a), &Value::Exact(ref b)) => a.div(b).map(Value::Exact).ok_or_else(divide_by_zero),
+            _ if other.to_f64() == 0.0 => Err(divide_by_zero()),
+            _ => Ok(Value::Float(self.to_f64() / other.to_f64())),
+        }
+    }
+
+    /// The value as an integer, for operators which only accept integers.
+    pub fn to_integer(&self, operator: &'static str) -> Result<BigInt, ErrorKind> {
+        match *try!(self.unitless()) {
+            Value::Float(f) => BigInt::from_f64(f),
+            Value::Exact(ref r) if r.is_integer() => Some(r.numerator().clone()),
+            _ => None,
+        }.ok_or(ErrorKind::IntegerRequired(operator))
+    }
+
+    // Integers stay exact if both operands were exact
+    fn integer_result(&self, other: &Value, n: BigInt) -> Value {
+        match (self, other) {
+            (&Value::Exact(_), &Value::Exact(_)) => Value::Exact(Rational::from_integer(n)),
+            _ => Value::Float(n.to_f64()),
+        }
+    }
+
+    fn integer_op<F>(&self, other: &Value, operator: &'static str, op: F) -> Result<Value, ErrorKind>
+        where F: Fn(&BigInt, &BigInt) -> Result<BigInt, ErrorKind>
+    {
+        let a = try!(self.to_integer(operator));
+        let b = try!(other.to_integer(operator));
+        op(&a, &b).map(|n| self.integer_result(other, n))
+    }
+
+    /// Floored integer division.
+    pub fn int_div(&self, other: &Value) -> Result<Value, ErrorKind> {
+        self.integer_op(other, "//", |a, b| {
+            if b.is_zero() {
+                Err(ErrorKind::OtherError("Divide by zero error".to_owned()))
+            } else {
+                Ok(a.div_floor(b).0)
+            }
+        })
+    }
+
+    /// The remainder of floored division, which has the sign of the divisor.
+    pub fn modulo(&self, other: &Value) -> Result<Value, ErrorKind> {
+        self.integer_op(other, "%", |a, b| {
+            if b.is_zero() {
+                Err(ErrorKind::OtherError("Divide by zero error".to_owned()))
+            } else {
+                Ok(a.div_floor(b).1)
+            }
+        })
+    }
+
+    pub fn bit_and(&self, other: &Value) -> Result<Value, ErrorKind> {
+        self.integer_op(other, "&", |a, b| Ok(a.bit_and(b)))
+    }
+
+    pub fn bit_or(&self, other: &Value) -> Result<Value, ErrorKind> {
+        self.integer_op(other, "|", |a, b| Ok(a.bit_or(b)))
+    }
+
+    pub fn bit_xor(&self, other: &Value) -> Result<Value, ErrorKind> {
+        self.integer_op(other, "xor", |a, b| Ok(a.bit_xor(b)))
+    }
+
+    pub fn bit_not(&self) -> Result<Value, ErrorKind> {
+        self.to_integer("~").map(|n| self.integer_result(self, n.bit_not()))
+    }
+
+    pub fn shl(&self, other: &Value) -> Result<Value, ErrorKind> {
+        self.integer_op(other, "<<", |a, b| shift_amount(b).map(|bits| a.shl(bits)))
+    }
+
+    pub fn shr(&self, other: &Value) -> Result<Value, ErrorKind> {
+        self.integer_op(other, ">>", |a, b| shift_amount(b).map(|bits| a.shr(bits)))
+    }
+
+    pub fn pow(&self, other: &Value) -> Result<Value, ErrorKind> {
+        let exponent = try!(other.unitless());
+        if let Value::Quantity(ref magnitude, ref unit) = *self {
+            let power = exponent.to_f64();
+            if power.fract() != 0.0 || power.abs() > 16.0 {
+                return Err(ErrorKind::OtherError("Quantities can only be raised to small integer powers".to_owned()));
+            }
+            let unit = Unit::base(unit.dimension.scale(power as i8));
+            return magnitude.pow(exponent).map(|m| Value::with_unit(m, unit));
+        }
+        match (self, other) {
+            (&Value::Exact(ref base), &Value::Exact(ref exponent)) => {
+                if !exponent.is_integer() {
+                    return Err(ErrorKind::OtherError("Exact mode only supports integer exponents".to_owned()));
+                }
+                let power = try!(exponent.numerator()
+                                         .abs()
+                                         .to_u64()
+                                         .ok_or_else(|| ErrorKind::OtherError("Exponent too large".to_owned())));
+                // Each bit of the base beyond the first adds `power` bits to the result
+                let bits = cmp::max(base.numerator().bits(), base.denominator().bits());
+                if bits.saturating_sub(1).saturating_mul(power) > MAX_POWER_BITS {
+                    return Err(ErrorKind::OtherError("Result too large".to_owned()));
+                }
+                let result = Rational::new(base.numerator().pow(power), base.denominator().pow(power));
+                if exponent.numerator().is_negative() {
+                    Rational::from_integer(BigInt::from_u64(1))
+                        .div(&result)
+                        .map(Value::Exact)
+                        .ok_or_else(|| ErrorKind::OtherError("Divide by zero error".to_owned()))
+                } else {
+                    Ok(Value::Exact(result))
+                }
+            }
+            // Fractional powers of negative numbers, such as (-8)^(1/3), are complex
+            _ if self.is_complex() || other.is_complex() || (self.to_f64() < 0.0 && other.to_f64().fract() != 0.0) => {
+                Ok(Value::from_complex(self.to_complex().pow(other.to_complex())))
+            }
+            _ => Ok(Value::Float(self.to_f64().powf(other.to_f64()))),
+        }
+    }
+}
+
+fn shift_amount(n: &BigInt) -> Result<u64, ErrorKind> {
+    if n.is_negative() {
+        return Err(ErrorKind::OtherError("Negative shift amount".to_owned()));
+    }
+    match n.to_u64() {
+        Some(bits) if bits <= 65536 => Ok(bits),
+        _ => Err(ErrorKind::OtherError("Shift amount too large".to_owned())),
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Exact(ref r) => write!(f, "{}", r),
+            Value::Complex(c) => write!(f, "{}", c),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Date(ref seconds) => write!(f, "{}", format_date(seconds.to_f64(), 0)),
+            Value::Quantity(ref magnitude, ref unit) if *unit == duration_unit() => {
+                write!(f, "{}", format_duration(magnitude.to_f64()))
+            }
+            Value::Quantity(ref magnitude, ref unit) => {
+                match magnitude.div(&Value::Exact(unit.factor.clone())) {
+                    Ok(n) => write!(f, "{} {}", n, unit.name),
+                    Err(_) => write!(f, "{} {}", magnitude, unit.dimension.name()),
+                }
+            }
+        }
+    }
+}
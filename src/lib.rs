@@ -0,0 +1,6 @@
+//! Code shared by the utilities, which are otherwise single file binaries in `src/bin`.
+
+#![deny(warnings)]
+
+pub mod calc;
+pub mod regex;
@@ -0,0 +1,170 @@
+//! Searching for any of a set of plain strings at once with an Aho-Corasick automaton, so that a
+//! search takes time in proportion to the length of the text however many strings there are.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::Options;
+use super::parse::cases;
+use super::program::is_word;
+
+#[derive(Clone, Debug)]
+struct State {
+    next: HashMap<char, usize>,
+    // The longest proper suffix of this state's prefix which is also a prefix of some string
+    fail: usize,
+    // The lengths in characters of the strings which end at this state, including those ending at
+    // the states it fails to
+    outputs: Vec<usize>,
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            next: HashMap::new(),
+            fail: 0,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Literals {
+    // A trie of the strings, with the root first
+    states: Vec<State>,
+    longest: usize,
+    options: Options,
+}
+
+impl Literals {
+    pub fn new<S: AsRef<str>>(strings: &[S], options: Options) -> Literals {
+        let mut literals = Literals {
+            states: vec![State::new()],
+            longest: 0,
+            options: options,
+        };
+        for string in strings {
+            literals.insert(string.as_ref());
+        }
+        literals.link();
+        literals
+    }
+
+    fn fold(&self, c: char) -> char {
+        if self.options.ignore_case { cases(c)[0] } else { c }
+    }
+
+    fn insert(&mut self, string: &str) {
+        let mut state = 0;
+        let mut length = 0;
+        for c in string.chars() {
+            let c = self.fold(c);
+            state = match self.states[state].next.get(&c) {
+                Some(&next) => next,
+                None => {
+                    self.states.push(State::new());
+                    let next = self.states.len() - 1;
+                    self.states[state].next.insert(c, next);
+                    next
+                }
+            };
+            length += 1;
+        }
+        self.states[state].outputs.push(length);
+        if length > self.longest {
+            self.longest = length;
+        }
+    }
+
+    // Set the failure links breadth first, so that each state's failure is linked before its
+    // children need it
+    fn link(&mut self) {
+        let mut queue: VecDeque<usize> = self.states[0].next.values().cloned().collect();
+        // An empty string ends everywhere
+        let empty = self.states[0].outputs.clone();
+        for &state in &queue {
+            self.states[state].outputs.extend(empty.iter().cloned());
+        }
+
+        while let Some(state) = queue.pop_front() {
+            let edges: Vec<(char, usize)> = self.states[state].next.iter().map(|(&c, &next)| (c, next)).collect();
+            for (c, next) in edges {
+                let mut fail = self.states[state].fail;
+                while fail != 0 && !self.states[fail].next.contains_key(&c) {
+                    fail = self.states[fail].fail;
+                }
+                let target = self.states[fail].next.get(&c).cloned().unwrap_or(0);
+                self.states[next].fail = target;
+                let inherited = self.states[target].outputs.clone();
+                self.states[next].outputs.extend(inherited);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    fn step(&self, mut state: usize, c: char) -> usize {
+        loop {
+            if let Some(&next) = self.states[state].next.get(&c) {
+                return next;
+            }
+            if state == 0 {
+                return 0;
+            }
+            state = self.states[state].fail;
+        }
+    }
+
+    // Whether a match from byte `start` to byte `end` of `text` meets the options
+    fn allowed(&self, text: &str, start: usize, end: usize) -> bool {
+        if self.options.whole_line {
+            start == 0 && end == text.len()
+        } else if self.options.whole_word {
+            !is_word(text[..start].chars().next_back()) && !is_word(text[end..].chars().next())
+        } else {
+            true
+        }
+    }
+
+    /// The leftmost, and then longest, occurrence of any of the strings which starts at or after
+    /// byte `start`.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        // Where each of the last few characters begins, as far back as the longest string reaches
+        let mut recent = VecDeque::with_capacity(self.longest);
+
+        // An empty string matches before the first character
+        let mut best = self.best(text, &recent, 0, start, None);
+        let mut state = 0;
+        for (i, c) in text[start..].char_indices() {
+            let (position, end) = (start + i, start + i + c.len_utf8());
+            if self.longest > 0 {
+                if recent.len() == self.longest {
+                    recent.pop_front();
+                }
+                recent.push_back(position);
+            }
+            // Matches ending after this character start too late to be better than the best
+            let earliest = if recent.len() < self.longest { start } else { recent.front().cloned().unwrap_or(end) };
+            if best.map_or(false, |(best_start, _)| earliest > best_start) {
+                break;
+            }
+            state = self.step(state, self.fold(c));
+            best = self.best(text, &recent, state, end, best);
+        }
+        best
+    }
+
+    // The better of `best` and the matches of the strings which end at `state`, at byte `end` of
+    // the text, given where the characters just before `end` begin
+    fn best(&self, text: &str, recent: &VecDeque<usize>, state: usize, end: usize, mut best: Option<(usize, usize)>)
+            -> Option<(usize, usize)> {
+        for &length in &self.states[state].outputs {
+            let match_start = if length == 0 { end } else { recent[recent.len() - length] };
+            let better = best.map_or(true, |(best_start, best_end)| {
+                match_start < best_start || match_start == best_start && end > best_end
+            });
+            if better && self.allowed(text, match_start, end) {
+                best = Some((match_start, end));
+            }
+        }
+        best
+    }
+}
@@ -0,0 +1,137 @@
+//! POSIX basic and extended regular expressions, as `grep` understands them.
+//!
+//! Expressions compile to an NFA which is simulated over the text, so there is no backtracking
+//! and no expression can make a search take exponential time. Back-references, which cannot be
+//! matched that way, are not supported.
+//!
+//! ```
+//! use extrautils::regex::{Regex, Syntax};
+//!
+//! let regex = Regex::new("^(GET|POST) /api/[a-z]+", Syntax::Extended).unwrap();
+//! assert_eq!(regex.find("POST /api/users?id=7"), Some((0, 15)));
+//! assert!(!regex.is_match("PUT /api/users"));
+//! ```
+
+mod literal;
+mod parse;
+mod program;
+
+#[cfg(test)]
+mod test;
+
+use std::fmt;
+
+use self::literal::Literals;
+use self::parse::{Assertion, Class, Node, Parser};
+use self::program::Inst;
+
+/// Which rules a pattern is read with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    /// Basic regular expressions, where `\(`, `\)`, `\{`, `\}`, `\|`, `\+` and `\?` are operators.
+    Basic,
+    /// Extended regular expressions, where `(`, `)`, `{`, `}`, `|`, `+` and `?` are operators.
+    Extended,
+    /// A plain string, with no operators at all.
+    Fixed,
+}
+
+/// A pattern which could not be compiled, and why.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    message: &'static str,
+}
+
+impl Error {
+    fn new(message: &'static str) -> Error {
+        Error { message: message }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+/// How a pattern is matched, beyond the syntax it is written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Options {
+    pub ignore_case: bool,
+    /// Only match whole words, with no word character just before or after the match.
+    pub whole_word: bool,
+    /// Only match the whole of the text.
+    pub whole_line: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Matcher {
+    Program(Vec<Inst>),
+    Literals(Literals),
+}
+
+/// One or more patterns, compiled to be searched for together.
+#[derive(Clone, Debug)]
+pub struct Regex {
+    matcher: Matcher,
+    ignore_case: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str, syntax: Syntax) -> Result<Regex, Error> {
+        Regex::with_options(pattern, syntax, Options::default())
+    }
+
+    pub fn with_options(pattern: &str, syntax: Syntax, options: Options) -> Result<Regex, Error> {
+        Regex::any(&[pattern], syntax, options)
+    }
+
+    /// A regex matching wherever any of `patterns` does, or nowhere if there are none. Plain
+    /// strings are all searched for in one pass however many there are.
+    pub fn any<S: AsRef<str>>(patterns: &[S], syntax: Syntax, options: Options) -> Result<Regex, Error> {
+        if syntax == Syntax::Fixed {
+            return Ok(Regex {
+                matcher: Matcher::Literals(Literals::new(patterns, options)),
+                ignore_case: options.ignore_case,
+            });
+        }
+
+        let mut branches = Vec::with_capacity(patterns.len());
+        for pattern in patterns {
+            branches.push(try!(Parser::new(pattern.as_ref(), syntax).parse()));
+        }
+        let mut node = match branches.len() {
+            // A class with nothing in it, which never matches
+            0 => Node::Class(Class { negated: false, ranges: Vec::new(), named: Vec::new() }),
+            1 => branches.pop().unwrap(),
+            _ => Node::Alternate(branches),
+        };
+        if options.whole_line {
+            node = Node::Concat(vec![Node::Assert(Assertion::LineStart), node, Node::Assert(Assertion::LineEnd)]);
+        } else if options.whole_word {
+            node = Node::Concat(vec![Node::Assert(Assertion::NoWordBefore), node, Node::Assert(Assertion::NoWordAfter)]);
+        }
+        Ok(Regex {
+            matcher: Matcher::Program(try!(program::compile(&node))),
+            ignore_case: options.ignore_case,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    /// The byte range of the leftmost, and then longest, match in `text`.
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.find_at(text, 0)
+    }
+
+    /// Like `find`, but only for matches starting at or after byte `start`. The text before
+    /// `start` is still seen by anchors such as `^` and `\<`.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        match self.matcher {
+            Matcher::Program(ref program) => program::search(program, self.ignore_case, text, start),
+            Matcher::Literals(ref literals) => literals.find_at(text, start),
+        }
+    }
+}
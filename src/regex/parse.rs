@@ -0,0 +1,385 @@
+//! Reading POSIX basic and extended regular expressions into a syntax tree.
+
+use super::{Error, Syntax};
+
+// The largest count allowed in an interval such as `a{2,5}`, as in POSIX's RE_DUP_MAX
+const DUP_MAX: u32 = 255;
+
+/// A zero width condition on the text around a position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Assertion {
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+    WordStart,
+    WordEnd,
+    // No word character just before or just after, as around a match of `grep -w`
+    NoWordBefore,
+    NoWordAfter,
+}
+
+/// A character class such as `alpha`, as written inside brackets in `[[:alpha:]]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NamedClass {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    Word,
+    Xdigit,
+}
+
+static CLASS_NAMES: &'static [(&'static str, NamedClass)] = &[
+    ("alnum", NamedClass::Alnum), ("alpha", NamedClass::Alpha), ("blank", NamedClass::Blank),
+    ("cntrl", NamedClass::Cntrl), ("digit", NamedClass::Digit), ("graph", NamedClass::Graph),
+    ("lower", NamedClass::Lower), ("print", NamedClass::Print), ("punct", NamedClass::Punct),
+    ("space", NamedClass::Space), ("upper", NamedClass::Upper), ("xdigit", NamedClass::Xdigit),
+];
+
+impl NamedClass {
+    pub fn contains(&self, c: char) -> bool {
+        match *self {
+            NamedClass::Alnum => c.is_alphanumeric(),
+            NamedClass::Alpha => c.is_alphabetic(),
+            NamedClass::Blank => c == ' ' || c == '\t',
+            NamedClass::Cntrl => c.is_control(),
+            NamedClass::Digit => c.is_digit(10),
+            NamedClass::Graph => !c.is_control() && !c.is_whitespace(),
+            NamedClass::Lower => c.is_lowercase(),
+            NamedClass::Print => !c.is_control(),
+            NamedClass::Punct => c.is_ascii() && !c.is_control() && !c.is_alphanumeric() && c != ' ',
+            NamedClass::Space => c.is_whitespace(),
+            NamedClass::Upper => c.is_uppercase(),
+            NamedClass::Word => c.is_alphanumeric() || c == '_',
+            NamedClass::Xdigit => c.is_digit(16),
+        }
+    }
+}
+
+// The lower and upper case forms of a character, where each is a single character
+pub fn cases(c: char) -> [char; 2] {
+    fn single<I: Iterator<Item = char>>(mut chars: I, c: char) -> char {
+        match (chars.next(), chars.next()) {
+            (Some(single), None) => single,
+            _ => c,
+        }
+    }
+    [single(c.to_lowercase(), c), single(c.to_uppercase(), c)]
+}
+
+/// A bracket expression such as `[a-z_]` or `[^[:space:]]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Class {
+    pub negated: bool,
+    pub ranges: Vec<(char, char)>,
+    pub named: Vec<NamedClass>,
+}
+
+impl Class {
+    fn named(class: NamedClass, negated: bool) -> Class {
+        Class {
+            negated: negated,
+            ranges: Vec::new(),
+            named: vec![class],
+        }
+    }
+
+    // Whether the class matches `c`, or when ignoring case any of its forms
+    pub fn matches(&self, c: char, ignore_case: bool) -> bool {
+        let contains = |c: char| {
+            self.ranges.iter().any(|&(low, high)| low <= c && c <= high) ||
+                self.named.iter().any(|class| class.contains(c))
+        };
+        let found = if ignore_case { cases(c).iter().any(|&c| contains(c)) } else { contains(c) };
+        found != self.negated
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    // The node, and the least and greatest number of times it may repeat
+    Repeat(Box<Node>, u32, Option<u32>),
+}
+
+pub struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    syntax: Syntax,
+    // How many groups are open at the current position
+    depth: usize,
+}
+
+impl Parser {
+    pub fn new(pattern: &str, syntax: Syntax) -> Parser {
+        Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            syntax: syntax,
+            depth: 0,
+        }
+    }
+
+    pub fn parse(mut self) -> Result<Node, Error> {
+        let node = try!(self.alternation());
+        if self.pos < self.chars.len() {
+            // Only a stray closing parenthesis stops an alternation early
+            return Err(Error::new("Unmatched ) or \\)"));
+        }
+        Ok(node)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).cloned()
+    }
+
+    fn extended(&self) -> bool {
+        self.syntax == Syntax::Extended
+    }
+
+    // Whether the input continues with an operator: a plain character in extended syntax, or one
+    // escaped with a backslash in basic syntax
+    fn at_operator(&self, operator: char) -> bool {
+        if self.extended() {
+            self.peek() == Some(operator)
+        } else {
+            self.peek() == Some('\\') && self.peek_at(1) == Some(operator)
+        }
+    }
+
+    fn skip_operator(&mut self) {
+        self.pos += if self.extended() { 1 } else { 2 };
+    }
+
+    fn alternation(&mut self) -> Result<Node, Error> {
+        let mut branches = vec![try!(self.concatenation())];
+        while self.at_operator('|') {
+            self.skip_operator();
+            branches.push(try!(self.concatenation()));
+        }
+        Ok(if branches.len() == 1 { branches.pop().unwrap() } else { Node::Alternate(branches) })
+    }
+
+    fn concatenation(&mut self) -> Result<Node, Error> {
+        let mut items = Vec::new();
+        while self.pos < self.chars.len() && !self.at_operator('|') && !self.at_operator(')') {
+            let atom = try!(self.atom(items.is_empty()));
+            // A repetition operator straight after a leading `^` stands for itself
+            let atom = if atom == Node::Assert(Assertion::LineStart) { atom } else { try!(self.repetitions(atom)) };
+            items.push(atom);
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.pop().unwrap(),
+            _ => Node::Concat(items),
+        })
+    }
+
+    // Whether the expression, a group or a branch ends at the current position, which is the only
+    // place a `$` just before it is an anchor in basic syntax
+    fn at_end_of_branch(&self) -> bool {
+        self.pos == self.chars.len() ||
+            (self.depth > 0 && self.at_operator(')')) || self.at_operator('|')
+    }
+
+    // The next item of a branch. Repetition operators only get this far when there is nothing
+    // before them to repeat, and then they match themselves.
+    fn atom(&mut self, first: bool) -> Result<Node, Error> {
+        let c = self.chars[self.pos];
+        self.pos += 1;
+        match c {
+            '.' => Ok(Node::Any),
+            '[' => self.bracket(),
+            '^' if self.extended() || first => Ok(Node::Assert(Assertion::LineStart)),
+            '$' if self.extended() || self.at_end_of_branch() => Ok(Node::Assert(Assertion::LineEnd)),
+            '(' if self.extended() => self.group(),
+            '\\' => self.escape(),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, Error> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(Error::new("Trailing backslash")),
+        };
+        self.pos += 1;
+        match c {
+            '(' if !self.extended() => self.group(),
+            c if c.is_digit(10) && c != '0' => Err(Error::new("Back-references are not supported")),
+            'w' => Ok(Node::Class(Class::named(NamedClass::Word, false))),
+            'W' => Ok(Node::Class(Class::named(NamedClass::Word, true))),
+            's' => Ok(Node::Class(Class::named(NamedClass::Space, false))),
+            'S' => Ok(Node::Class(Class::named(NamedClass::Space, true))),
+            'b' => Ok(Node::Assert(Assertion::WordBoundary)),
+            'B' => Ok(Node::Assert(Assertion::NotWordBoundary)),
+            '<' => Ok(Node::Assert(Assertion::WordStart)),
+            '>' => Ok(Node::Assert(Assertion::WordEnd)),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn group(&mut self) -> Result<Node, Error> {
+        self.depth += 1;
+        let node = try!(self.alternation());
+        if !self.at_operator(')') {
+            return Err(Error::new("Unmatched ( or \\("));
+        }
+        self.skip_operator();
+        self.depth -= 1;
+        Ok(node)
+    }
+
+    fn repetitions(&mut self, mut node: Node) -> Result<Node, Error> {
+        loop {
+            let (min, max) = if self.peek() == Some('*') {
+                self.pos += 1;
+                (0, None)
+            } else if self.at_operator('+') {
+                self.skip_operator();
+                (1, None)
+            } else if self.at_operator('?') {
+                self.skip_operator();
+                (0, Some(1))
+            } else if self.at_operator('{') && (!self.extended() || self.peek_at(1).map_or(false, |c| c.is_digit(10))) {
+                self.skip_operator();
+                try!(self.interval())
+            } else {
+                return Ok(node);
+            };
+            node = Node::Repeat(Box::new(node), min, max);
+        }
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().map_or(false, |c| c.is_digit(10)) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().cloned().collect();
+        digits.parse().ok()
+    }
+
+    // The bounds of an interval such as `{2}`, `{2,}` or `{2,5}`, after the opening brace
+    fn interval(&mut self) -> Result<(u32, Option<u32>), Error> {
+        let min = match self.number() {
+            Some(min) => min,
+            None => return Err(Error::new("Invalid content of \\{\\}")),
+        };
+        let max = if self.peek() == Some(',') {
+            self.pos += 1;
+            self.number()
+        } else {
+            Some(min)
+        };
+        if !self.at_operator('}') {
+            return Err(Error::new("Unmatched \\{"));
+        }
+        self.skip_operator();
+        match max {
+            _ if min > DUP_MAX => Err(Error::new("Regular expression too big")),
+            Some(max) if max > DUP_MAX => Err(Error::new("Regular expression too big")),
+            Some(max) if max < min => Err(Error::new("Invalid content of \\{\\}")),
+            max => Ok((min, max)),
+        }
+    }
+
+    // A bracket expression, after the opening bracket
+    fn bracket(&mut self) -> Result<Node, Error> {
+        let mut class = Class {
+            negated: false,
+            ranges: Vec::new(),
+            named: Vec::new(),
+        };
+        if self.peek() == Some('^') {
+            class.negated = true;
+            self.pos += 1;
+        }
+
+        let mut first = true;
+        loop {
+            let low = match self.peek() {
+                None => return Err(Error::new("Unmatched [, [^, [:, [., or [=")),
+                Some(']') if !first => {
+                    self.pos += 1;
+                    return Ok(Node::Class(class));
+                }
+                Some('[') if self.peek_at(1) == Some(':') => {
+                    self.pos += 2;
+                    let name = try!(self.bracket_term(':'));
+                    match CLASS_NAMES.iter().find(|&&(n, _)| n == name) {
+                        Some(&(_, named)) => class.named.push(named),
+                        None => return Err(Error::new("Invalid character class name")),
+                    }
+                    first = false;
+                    continue;
+                }
+                Some(_) => try!(self.bracket_char()),
+            };
+            first = false;
+
+            if self.peek() == Some('-') && self.peek_at(1).map_or(false, |c| c != ']') {
+                self.pos += 1;
+                let high = try!(self.bracket_char());
+                if high < low {
+                    return Err(Error::new("Invalid range end"));
+                }
+                class.ranges.push((low, high));
+            } else {
+                class.ranges.push((low, low));
+            }
+        }
+    }
+
+    // A single character in a bracket expression, which may be written `[.c.]` or `[=c=]`
+    fn bracket_char(&mut self) -> Result<char, Error> {
+        match (self.peek(), self.peek_at(1)) {
+            (Some('['), Some(delimiter)) if delimiter == '.' || delimiter == '=' => {
+                self.pos += 2;
+                let name = try!(self.bracket_term(delimiter));
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Ok(c),
+                    _ => Err(Error::new("Invalid collation character")),
+                }
+            }
+            (Some(c), _) => {
+                self.pos += 1;
+                Ok(c)
+            }
+            (None, _) => Err(Error::new("Unmatched [, [^, [:, [., or [=")),
+        }
+    }
+
+    // The text of `[:name:]`, `[.c.]` or `[=c=]` up to the closing delimiter and bracket
+    fn bracket_term(&mut self, delimiter: char) -> Result<String, Error> {
+        let start = self.pos;
+        while self.pos + 1 < self.chars.len() {
+            if self.chars[self.pos] == delimiter && self.chars[self.pos + 1] == ']' {
+                let term = self.chars[start..self.pos].iter().cloned().collect();
+                self.pos += 2;
+                return Ok(term);
+            }
+            self.pos += 1;
+        }
+        Err(Error::new("Unmatched [, [^, [:, [., or [="))
+    }
+}
@@ -0,0 +1,234 @@
+//! Compiling a syntax tree to a Thompson NFA, and running it over text in a single pass.
+//!
+//! Every thread of the automaton is stepped over each character together, so a search takes time
+//! proportional to the length of the text times the size of the program, however the expression
+//! is written.
+
+use std::mem;
+
+use super::Error;
+use super::parse::{cases, Assertion, Class, Node};
+
+// The most instructions an expression may compile to, which bounds the cost of a search
+const MAX_INSTRUCTIONS: usize = 100000;
+
+#[derive(Clone, Debug)]
+pub enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    // Continue at both instructions
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+pub fn compile(node: &Node) -> Result<Vec<Inst>, Error> {
+    let mut program = Vec::new();
+    try!(emit(node, &mut program));
+    program.push(Inst::Match);
+    Ok(program)
+}
+
+fn emit(node: &Node, program: &mut Vec<Inst>) -> Result<(), Error> {
+    if program.len() > MAX_INSTRUCTIONS {
+        return Err(Error::new("Regular expression too big"));
+    }
+    match *node {
+        Node::Empty => (),
+        Node::Char(c) => program.push(Inst::Char(c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(ref class) => program.push(Inst::Class(class.clone())),
+        Node::Assert(assertion) => program.push(Inst::Assert(assertion)),
+        Node::Concat(ref items) => {
+            for item in items {
+                try!(emit(item, program));
+            }
+        }
+        Node::Alternate(ref branches) => {
+            // Each branch but the last is tried through a split, and jumps past the rest at its end
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    try!(emit(branch, program));
+                } else {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    try!(emit(branch, program));
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    let next = program.len();
+                    program[split] = Inst::Split(split + 1, next);
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat(ref item, min, max) => {
+            for _ in 0..min {
+                try!(emit(item, program));
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    try!(emit(item, program));
+                    program.push(Inst::Jump(split));
+                    let end = program.len();
+                    program[split] = Inst::Split(split + 1, end);
+                }
+                Some(max) => {
+                    // Each optional copy may be skipped, straight to the end of the repetition
+                    let mut splits = Vec::new();
+                    for _ in min..max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        try!(emit(item, program));
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn is_word(c: Option<char>) -> bool {
+    c.map_or(false, |c| c.is_alphanumeric() || c == '_')
+}
+
+// Whether an assertion holds between the characters `before` and `after` a position
+fn holds(assertion: Assertion, before: Option<char>, after: Option<char>) -> bool {
+    match assertion {
+        Assertion::LineStart => before.is_none(),
+        Assertion::LineEnd => after.is_none(),
+        Assertion::WordBoundary => is_word(before) != is_word(after),
+        Assertion::NotWordBoundary => is_word(before) == is_word(after),
+        Assertion::WordStart => !is_word(before) && is_word(after),
+        Assertion::WordEnd => is_word(before) && !is_word(after),
+        Assertion::NoWordBefore => !is_word(before),
+        Assertion::NoWordAfter => !is_word(after),
+    }
+}
+
+// The threads at one position in the text: the instruction each is waiting at and where its match
+// started, without duplicates, in the order they were added
+struct Threads {
+    threads: Vec<(usize, usize)>,
+    // For each instruction, its index in `threads` if it has a thread
+    index: Vec<usize>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads {
+            threads: Vec::with_capacity(size),
+            index: vec![0; size],
+        }
+    }
+
+    fn contains(&self, pc: usize) -> bool {
+        let i = self.index[pc];
+        i < self.threads.len() && self.threads[i].0 == pc
+    }
+
+    fn insert(&mut self, pc: usize, start: usize) {
+        self.index[pc] = self.threads.len();
+        self.threads.push((pc, start));
+    }
+
+    fn clear(&mut self) {
+        self.threads.clear();
+    }
+}
+
+// Add a thread at `pc`, and every thread reachable from it without consuming a character
+fn add(program: &[Inst], list: &mut Threads, stack: &mut Vec<usize>, pc: usize, start: usize,
+       before: Option<char>, after: Option<char>) {
+    stack.push(pc);
+    while let Some(pc) = stack.pop() {
+        if list.contains(pc) {
+            continue;
+        }
+        list.insert(pc, start);
+        match program[pc] {
+            Inst::Jump(target) => stack.push(target),
+            Inst::Split(first, second) => {
+                stack.push(second);
+                stack.push(first);
+            }
+            Inst::Assert(assertion) => {
+                if holds(assertion, before, after) {
+                    stack.push(pc + 1);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Find the leftmost match of a program in `text` which starts at or after byte `start`, and the
+/// longest of those, as POSIX requires.
+pub fn search(program: &[Inst], ignore_case: bool, text: &str, start: usize) -> Option<(usize, usize)> {
+    let mut current = Threads::new(program.len());
+    let mut next = Threads::new(program.len());
+    let mut stack = Vec::new();
+    let mut best: Option<(usize, usize)> = None;
+
+    let mut pos = start;
+    let mut before = text[..start].chars().next_back();
+    loop {
+        let mut rest = text[pos..].chars();
+        let c = rest.next();
+        let after = rest.next();
+        // Threads are kept in order of where their match started, so that when two reach the
+        // same instruction the one which started first wins
+        if best.is_none() {
+            add(program, &mut current, &mut stack, 0, pos, before, c);
+        }
+
+        for i in 0..current.threads.len() {
+            let (pc, thread_start) = current.threads[i];
+            if best.map_or(false, |(best_start, _)| thread_start > best_start) {
+                break;
+            }
+            let step = match program[pc] {
+                Inst::Match => {
+                    if best.map_or(true, |(best_start, best_end)| thread_start < best_start || pos > best_end) {
+                        best = Some((thread_start, pos));
+                    }
+                    false
+                }
+                Inst::Char(expected) => {
+                    c.map_or(false, |c| c == expected || ignore_case && cases(c) == cases(expected))
+                }
+                Inst::Any => c.is_some(),
+                Inst::Class(ref class) => c.map_or(false, |c| class.matches(c, ignore_case)),
+                _ => false,
+            };
+            if step {
+                add(program, &mut next, &mut stack, pc + 1, thread_start, c, after);
+            }
+        }
+
+        match c {
+            Some(c) => {
+                pos += c.len_utf8();
+                before = Some(c);
+            }
+            None => break,
+        }
+        mem::swap(&mut current, &mut next);
+        next.clear();
+        if current.threads.is_empty() && best.is_some() {
+            break;
+        }
+    }
+    best
+}
@@ -0,0 +1,200 @@
+use super::*;
+
+fn find(pattern: &str, syntax: Syntax, text: &str) -> Option<(usize, usize)> {
+    Regex::new(pattern, syntax).unwrap().find(text)
+}
+
+fn ere<'a>(pattern: &str, text: &'a str) -> Option<&'a str> {
+    find(pattern, Syntax::Extended, text).map(|(start, end)| &text[start..end])
+}
+
+fn bre<'a>(pattern: &str, text: &'a str) -> Option<&'a str> {
+    find(pattern, Syntax::Basic, text).map(|(start, end)| &text[start..end])
+}
+
+#[test]
+fn literals() {
+    assert_eq!(ere("cat", "concatenate"), Some("cat"));
+    assert_eq!(ere("dog", "concatenate"), None);
+    assert_eq!(ere("", "anything"), Some(""));
+    assert_eq!(ere("é+", "café crème"), Some("é"));
+    assert_eq!(find("a.c", Syntax::Fixed, "abc a.c"), Some((4, 7)));
+    assert_eq!(find("(x)*", Syntax::Fixed, "f(x)*2"), Some((1, 5)));
+}
+
+#[test]
+fn leftmost_longest() {
+    assert_eq!(ere("a|ab|abc", "xabcd"), Some("abc"));
+    assert_eq!(ere("(a|ab)(c|bcd)", "abcd"), Some("abcd"));
+    assert_eq!(ere("b+", "abbbc bbbbb"), Some("bbb"));
+    assert_eq!(ere("x*", "abc"), Some(""));
+    assert_eq!(find("[0-9]+", Syntax::Extended, "id 42 and 1234"), Some((3, 5)));
+}
+
+#[test]
+fn repetition() {
+    assert_eq!(ere("ab*c", "ac abc abbc"), Some("ac"));
+    assert_eq!(ere("ab+c", "ac abc abbc"), Some("abc"));
+    assert_eq!(ere("colou?r", "the colour red"), Some("colour"));
+    assert_eq!(ere("a{3}", "aa aaaa"), Some("aaa"));
+    assert_eq!(ere("a{2,}", "a aaaaa"), Some("aaaaa"));
+    assert_eq!(ere("a{2,3}", "a aaaaa"), Some("aaa"));
+    assert_eq!(ere("(ab){2}", "ab abab"), Some("abab"));
+    assert_eq!(ere("(a*)*b", "aaab"), Some("aaab"));
+    assert_eq!(ere("(|a)+b", "aab"), Some("aab"));
+}
+
+#[test]
+fn anchors() {
+    assert_eq!(ere("^abc", "abcabc"), Some("abc"));
+    assert_eq!(find("abc$", Syntax::Extended, "abcabc"), Some((3, 6)));
+    assert_eq!(ere("^$", ""), Some(""));
+    assert_eq!(ere("^b", "ab"), None);
+    assert_eq!(ere("a^b", "a^b"), None);
+    assert_eq!(ere("(^a|b)", "cab"), Some("b"));
+    assert_eq!(Regex::new("^a", Syntax::Extended).unwrap().find_at("aaa", 1), None);
+}
+
+#[test]
+fn word_assertions() {
+    assert_eq!(find("\\<is\\>", Syntax::Extended, "this is it"), Some((5, 7)));
+    assert_eq!(find("\\bit", Syntax::Extended, "bit it"), Some((4, 6)));
+    assert_eq!(find("\\Bit", Syntax::Extended, "it bit"), Some((4, 6)));
+    assert_eq!(ere("\\w+", "  snake_case2 "), Some("snake_case2"));
+    assert_eq!(ere("\\S+", "  a-b "), Some("a-b"));
+    assert_eq!(Regex::new("\\<b", Syntax::Extended).unwrap().find_at("ab b", 1), Some((3, 4)));
+}
+
+#[test]
+fn bracket_expressions() {
+    assert_eq!(ere("[abc]+", "xxbcaxx"), Some("bca"));
+    assert_eq!(ere("[^a-z ]+", "abc DEF ghi"), Some("DEF"));
+    assert_eq!(ere("[]x]+", "a]x]b"), Some("]x]"));
+    assert_eq!(ere("[^]a]", "]ab"), Some("b"));
+    assert_eq!(ere("[a-]+", "b-a-c"), Some("-a-"));
+    assert_eq!(ere("[[:digit:][:upper:]]+", "abc12XYz"), Some("12XY"));
+    assert_eq!(ere("[[:space:]]", "a\tb"), Some("\t"));
+    assert_eq!(ere("[[:punct:]]+", "hi!?"), Some("!?"));
+    assert_eq!(ere("[[.-.]]", "a-b"), Some("-"));
+    assert_eq!(ere("[\\]+", "a\\\\b"), Some("\\\\"));
+    assert_eq!(ere("[.*+]+", "a.*+b"), Some(".*+"));
+}
+
+#[test]
+fn basic_syntax() {
+    assert_eq!(bre("a+b", "aab a+b"), Some("a+b"));
+    assert_eq!(bre("a\\+b", "aab"), Some("aab"));
+    assert_eq!(bre("\\(ab\\)*c", "ababc"), Some("ababc"));
+    assert_eq!(bre("(ab)", "x(ab)"), Some("(ab)"));
+    assert_eq!(bre("a\\{2\\}", "a aa"), Some("aa"));
+    assert_eq!(bre("a{2}", "aa a{2}"), Some("a{2}"));
+    assert_eq!(bre("cat\\|dog", "hotdog"), Some("dog"));
+    assert_eq!(bre("*a", "b*a"), Some("*a"));
+    assert_eq!(bre("^*", "*a"), Some("*"));
+    assert_eq!(bre("a^b", "a^b"), Some("a^b"));
+    assert_eq!(bre("a$b", "a$b"), Some("a$b"));
+    assert_eq!(bre("\\(a$\\)", "ba"), Some("a"));
+    assert_eq!(bre("x\\?y", "y"), Some("y"));
+}
+
+#[test]
+fn operators_without_operands() {
+    assert_eq!(ere("*a", "b*a"), Some("*a"));
+    assert_eq!(ere("+", "1+1"), Some("+"));
+    assert_eq!(ere("a|*b", "*b"), Some("*b"));
+    assert_eq!(ere("a{", "a{"), Some("a{"));
+    assert_eq!(ere("a{x}", "a{x}"), Some("a{x}"));
+    assert_eq!(ere("()", "a"), Some(""));
+}
+
+#[test]
+fn options() {
+    let find = |pattern: &str, syntax: Syntax, options: Options, text: &str| {
+        Regex::with_options(pattern, syntax, options).unwrap().find(text)
+    };
+    let ignore_case = Options { ignore_case: true, ..Options::default() };
+    assert_eq!(find("hello", Syntax::Basic, ignore_case, "Say HeLLo"), Some((4, 9)));
+    assert_eq!(find("[a-c]+", Syntax::Extended, ignore_case, "xABCx"), Some((1, 4)));
+    assert_eq!(find("[^a]", Syntax::Extended, ignore_case, "Ab"), Some((1, 2)));
+    assert_eq!(find("ÉTÉ", Syntax::Fixed, ignore_case, "un été"), Some((3, 8)));
+
+    let whole_word = Options { whole_word: true, ..Options::default() };
+    assert_eq!(find("is", Syntax::Basic, whole_word, "this is"), Some((5, 7)));
+    assert_eq!(find("a.", Syntax::Basic, whole_word, "abc ab"), Some((4, 6)));
+    assert_eq!(find("-x", Syntax::Fixed, whole_word, "a-x a -x"), Some((6, 8)));
+    assert_eq!(find("foo", Syntax::Basic, whole_word, "foobar foo_"), None);
+
+    let whole_line = Options { whole_line: true, ..Options::default() };
+    assert_eq!(find("a|ab", Syntax::Extended, whole_line, "ab"), Some((0, 2)));
+    assert_eq!(find("b", Syntax::Basic, whole_line, "ab"), None);
+}
+
+#[test]
+fn several_patterns() {
+    let any = |patterns: &[&str], syntax: Syntax, text: &str| {
+        Regex::any(patterns, syntax, Options::default()).unwrap().find(text)
+    };
+    assert_eq!(any(&["cat", "dog"], Syntax::Basic, "hotdog or cat"), Some((3, 6)));
+    assert_eq!(any(&["a\\+", "b*"], Syntax::Basic, "xaab"), Some((0, 0)));
+    assert_eq!(any(&["ab", "abc", "b"], Syntax::Fixed, "xabcd"), Some((1, 4)));
+    assert_eq!(any(&["he", "she", "his", "hers"], Syntax::Fixed, "ushers"), Some((1, 4)));
+    assert_eq!(any(&["bcd", "abcdx", "c"], Syntax::Fixed, "abcdy"), Some((1, 4)));
+    assert_eq!(any(&["", "x"], Syntax::Fixed, "ab"), Some((0, 0)));
+    assert_eq!(any(&["é", "ü"], Syntax::Fixed, "naïve über"), Some((7, 9)));
+    assert_eq!(any(&[], Syntax::Extended, "anything"), None);
+    assert_eq!(any(&[], Syntax::Fixed, "anything"), None);
+    assert!(Regex::any(&["ok", "(bad"], Syntax::Extended, Options::default()).is_err());
+
+    // Hundreds of strings searched for at once
+    let ids: Vec<String> = (0..500).map(|n| format!("ID-{:04}", n * 7)).collect();
+    let regex = Regex::any(&ids, Syntax::Fixed, Options::default()).unwrap();
+    assert_eq!(regex.find("request ID-0004 ID-0343 done"), Some((16, 23)));
+    assert_eq!(regex.find("ID-0001 ID-0002"), None);
+
+    let options = Options { ignore_case: true, whole_word: true, ..Options::default() };
+    let regex = Regex::any(&["Foo", "foo bar"], Syntax::Fixed, options).unwrap();
+    assert_eq!(regex.find("foobar FOO BAR"), Some((7, 14)));
+    assert_eq!(regex.find_at("FOO BARS", 0), Some((0, 3)));
+    let whole_line = Options { whole_line: true, ..Options::default() };
+    assert_eq!(Regex::any(&["ab", "b"], Syntax::Fixed, whole_line).unwrap().find("ab"), Some((0, 2)));
+    assert_eq!(Regex::any(&["a"], Syntax::Fixed, whole_line).unwrap().find("ab"), None);
+}
+
+#[test]
+fn errors() {
+    let error = |pattern: &str, syntax: Syntax| Regex::new(pattern, syntax).unwrap_err().to_string();
+    assert_eq!(error("(ab", Syntax::Extended), "Unmatched ( or \\(");
+    assert_eq!(error("\\(ab", Syntax::Basic), "Unmatched ( or \\(");
+    assert_eq!(error("ab)", Syntax::Extended), "Unmatched ) or \\)");
+    assert_eq!(error("[ab", Syntax::Extended), "Unmatched [, [^, [:, [., or [=");
+    assert_eq!(error("[[:alpah:]]", Syntax::Extended), "Invalid character class name");
+    assert_eq!(error("[z-a]", Syntax::Extended), "Invalid range end");
+    assert_eq!(error("a{3,2}", Syntax::Extended), "Invalid content of \\{\\}");
+    assert_eq!(error("a\\{2", Syntax::Basic), "Unmatched \\{");
+    assert_eq!(error("a{1000}", Syntax::Extended), "Regular expression too big");
+    assert_eq!(error("((a{255}){255}){255}", Syntax::Extended), "Regular expression too big");
+    assert_eq!(error("ab\\", Syntax::Extended), "Trailing backslash");
+    assert_eq!(error("\\(a\\)\\1", Syntax::Basic), "Back-references are not supported");
+    assert!(Regex::new("(ab", Syntax::Fixed).is_ok());
+}
+
+#[test]
+fn no_exponential_blowup() {
+    // Backtracking matchers take exponential time on these
+    let text: String = ::std::iter::repeat('a').take(30).collect();
+    let pattern = ["(a?){30}", &::std::iter::repeat("a").take(30).collect::<String>()].concat();
+    assert_eq!(find(&pattern, Syntax::Extended, &text), Some((0, 30)));
+    assert_eq!(find("(a*)*b", Syntax::Extended, &text), None);
+    assert_eq!(find("(a|aa)+$", Syntax::Extended, &text), Some((0, 30)));
+
+    // Finding every match in a long line only reads as far as each match
+    let line: String = ::std::iter::repeat("xab").take(100000).collect();
+    let regex = Regex::any(&["ab", "b", "abxabxabc"], Syntax::Fixed, Options::default()).unwrap();
+    let mut start = 0;
+    let mut count = 0;
+    while let Some((_, end)) = regex.find_at(&line, start) {
+        start = end;
+        count += 1;
+    }
+    assert_eq!(count, 100000);
+}